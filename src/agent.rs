@@ -0,0 +1,639 @@
+use crate::sso::SsoToken;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::PathBuf;
+
+// Overrides the socket location, like SSH_AUTH_SOCK for ssh-agent
+const SOCKET_ENV: &str = "AAA_AGENT_SOCKET";
+pub const DEFAULT_IDLE_TIMEOUT_MINUTES: u64 = 60;
+// Clients give up quickly so a stuck agent never blocks a login
+#[cfg(unix)]
+const CLIENT_TIMEOUT_SECONDS: u64 = 2;
+
+// Session credentials as held by the agent; they are never written to disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+    expiration: DateTime<Utc>,
+}
+
+impl SessionCredentials {
+    // Only temporary credentials are worth keeping, long-term keys stay in ~/.aws/credentials
+    fn from_credentials(credentials: &Credentials) -> Option<Self> {
+        Some(SessionCredentials {
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            session_token: credentials.session_token().map(String::from),
+            expiration: DateTime::<Utc>::from(credentials.expiry()?),
+        })
+    }
+
+    fn into_credentials(self) -> Credentials {
+        Credentials::new(
+            self.access_key_id,
+            self.secret_access_key,
+            self.session_token,
+            Some(self.expiration.into()),
+            "aaa-agent",
+        )
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.expiration > Utc::now() + Duration::minutes(crate::SESSION_REFRESH_MARGIN_MINUTES)
+    }
+}
+
+// The same profile name may stand for another account in other config and
+// credentials files, so sessions are told apart by those files as well
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct SessionKey {
+    config_file: PathBuf,
+    credentials_file: PathBuf,
+    profile: String,
+}
+
+impl SessionKey {
+    fn new(profile_name: &str) -> Option<Self> {
+        Some(SessionKey {
+            config_file: std::path::absolute(crate::get_aws_config_path().ok()?).ok()?,
+            credentials_file: std::path::absolute(crate::get_aws_credentials_path().ok()?).ok()?,
+            profile: profile_name.to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SessionInfo {
+    session: SessionKey,
+    expiration: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TokenInfo {
+    start_url: String,
+    expires_at: String,
+}
+
+// Everything the agent holds, in memory only
+#[cfg(unix)]
+#[derive(Default)]
+struct Held {
+    sessions: std::collections::HashMap<SessionKey, SessionCredentials>,
+    // SSO tokens by start URL, shared by all profiles signing in there
+    tokens: std::collections::HashMap<String, SsoToken>,
+}
+
+// One JSON line per connection in each direction
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+enum Request {
+    Get {
+        session: SessionKey,
+    },
+    Store {
+        session: SessionKey,
+        credentials: SessionCredentials,
+    },
+    GetToken {
+        start_url: String,
+    },
+    StoreToken {
+        start_url: String,
+        token: SsoToken,
+    },
+    Status,
+    Lock,
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "kebab-case")]
+enum Response {
+    Credentials {
+        credentials: Option<SessionCredentials>,
+    },
+    Token {
+        token: Option<SsoToken>,
+    },
+    Status {
+        pid: u32,
+        idle_timeout_minutes: u64,
+        sessions: Vec<SessionInfo>,
+        tokens: Vec<TokenInfo>,
+    },
+    Cleared {
+        sessions: usize,
+        tokens: usize,
+    },
+    Ok,
+}
+
+// $XDG_RUNTIME_DIR/aaa/agent.sock unless overridden
+pub fn socket_path() -> Result<PathBuf> {
+    if let Some(path) = env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return Ok(PathBuf::from(path));
+    }
+
+    Ok(crate::session::runtime_dir()?.join("agent.sock"))
+}
+
+#[cfg(unix)]
+async fn send(request: &Request) -> Result<Response> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UnixStream;
+
+    let exchange = async {
+        let mut stream = UnixStream::connect(socket_path()?).await?;
+
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        stream.write_all(&line).await?;
+        stream.shutdown().await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+
+        serde_json::from_slice(&response).context("Invalid response from the aaa agent")
+    };
+
+    tokio::time::timeout(
+        std::time::Duration::from_secs(CLIENT_TIMEOUT_SECONDS),
+        exchange,
+    )
+    .await
+    .map_err(|_| anyhow!("The aaa agent did not respond"))?
+}
+
+#[cfg(not(unix))]
+async fn send(_request: &Request) -> Result<Response> {
+    Err(anyhow!("The aaa agent is only supported on Unix"))
+}
+
+// Session credentials the agent holds for a profile, if it runs and they stay
+// valid for a while
+pub async fn get(profile_name: &str) -> Option<Credentials> {
+    let request = Request::Get {
+        session: SessionKey::new(profile_name)?,
+    };
+
+    match send(&request).await {
+        Ok(Response::Credentials {
+            credentials: Some(credentials),
+        }) if credentials.is_fresh() => Some(credentials.into_credentials()),
+        _ => None,
+    }
+}
+
+// Hands new session credentials to the agent; returns whether it holds them
+// now, false without a running agent
+pub async fn store(profile_name: &str, credentials: &Credentials) -> bool {
+    let Some(credentials) = SessionCredentials::from_credentials(credentials) else {
+        return false;
+    };
+    let Some(session) = SessionKey::new(profile_name) else {
+        return false;
+    };
+
+    matches!(
+        send(&Request::Store {
+            session,
+            credentials,
+        })
+        .await,
+        Ok(Response::Ok)
+    )
+}
+
+// The SSO token the agent holds for a start URL, if it runs and has one
+pub async fn get_token(start_url: &str) -> Option<SsoToken> {
+    let request = Request::GetToken {
+        start_url: start_url.to_string(),
+    };
+
+    match send(&request).await {
+        Ok(Response::Token { token }) => token,
+        _ => None,
+    }
+}
+
+// Hands a new SSO token to the agent; returns whether it holds it now, in
+// which case it need not go to the token cache on disk
+pub async fn store_token(start_url: &str, token: &SsoToken) -> bool {
+    let request = Request::StoreToken {
+        start_url: start_url.to_string(),
+        token: token.clone(),
+    };
+
+    matches!(send(&request).await, Ok(Response::Ok))
+}
+
+pub async fn status() -> Result<()> {
+    let path = socket_path()?;
+    let Ok(response) = send(&Request::Status).await else {
+        println!("{}", "aaa agent is not running".yellow());
+        return Ok(());
+    };
+
+    let Response::Status {
+        pid,
+        idle_timeout_minutes,
+        sessions,
+        tokens,
+    } = response
+    else {
+        return Err(anyhow!("Unexpected response from the aaa agent"));
+    };
+
+    println!(
+        "{}",
+        format!("✓ aaa agent is running (pid {})", pid)
+            .green()
+            .bold()
+    );
+    println!("{} {}", "Socket:".bold(), path.display());
+    if idle_timeout_minutes == 0 {
+        println!("{} never", "Idle timeout:".bold());
+    } else {
+        println!(
+            "{} {} minutes",
+            "Idle timeout:".bold(),
+            idle_timeout_minutes
+        );
+    }
+    println!();
+
+    if sessions.is_empty() && tokens.is_empty() {
+        println!("{}", "No sessions held".dimmed());
+        return Ok(());
+    }
+
+    if !tokens.is_empty() {
+        println!("{}", "SSO sessions:".bold());
+        for token in tokens {
+            println!(
+                "  {} {}",
+                token.start_url.green(),
+                format!("expires at {}", token.expires_at).dimmed()
+            );
+        }
+    }
+
+    if sessions.is_empty() {
+        return Ok(());
+    }
+
+    println!("{}", "Sessions:".bold());
+    for session in sessions {
+        let remaining = (session.expiration - Utc::now()).num_minutes();
+        println!(
+            "  {} {}",
+            session.session.profile.green(),
+            format!(
+                "expires at {} ({} minutes left)",
+                session.expiration.to_rfc3339(),
+                remaining
+            )
+            .dimmed()
+        );
+        println!(
+            "    {}",
+            format!(
+                "from {} and {}",
+                session.session.config_file.display(),
+                session.session.credentials_file.display()
+            )
+            .dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+pub async fn lock() -> Result<()> {
+    match send(&Request::Lock).await {
+        Ok(Response::Cleared { sessions, tokens }) => {
+            println!(
+                "{}",
+                format!(
+                    "✓ aaa agent locked, {} session(s) and {} SSO token(s) cleared",
+                    sessions, tokens
+                )
+                .green()
+            );
+            Ok(())
+        }
+        Ok(_) => Err(anyhow!("Unexpected response from the aaa agent")),
+        Err(_) => {
+            println!("{}", "aaa agent is not running".yellow());
+            Ok(())
+        }
+    }
+}
+
+pub async fn stop() -> Result<()> {
+    match send(&Request::Stop).await {
+        Ok(_) => {
+            println!("{}", "✓ aaa agent stopped".green());
+            Ok(())
+        }
+        Err(_) => {
+            println!("{}", "aaa agent is not running".yellow());
+            Ok(())
+        }
+    }
+}
+
+// Starts the agent as a detached background process
+#[cfg(unix)]
+pub async fn start(idle_timeout_minutes: u64) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let path = socket_path()?;
+    if let Ok(Response::Status { pid, .. }) = send(&Request::Status).await {
+        println!(
+            "{}",
+            format!("✓ aaa agent is already running (pid {})", pid).green()
+        );
+        return Ok(());
+    }
+
+    let executable = env::current_exe().context("Failed to locate the aaa executable")?;
+
+    // A new process group keeps Ctrl+C in this terminal from reaching the agent
+    let child = Command::new(executable)
+        .args([
+            "agent",
+            "--foreground",
+            "--idle-timeout",
+            &idle_timeout_minutes.to_string(),
+        ])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to start the aaa agent")?;
+
+    // Wait for the socket so the next command can use the agent right away
+    for _ in 0..50 {
+        if send(&Request::Status).await.is_ok() {
+            println!(
+                "{}",
+                format!("✓ aaa agent started (pid {})", child.id())
+                    .green()
+                    .bold()
+            );
+            println!("{} {}", "Socket:".bold(), path.display());
+            return Ok(());
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    Err(anyhow!("The aaa agent did not start listening"))
+}
+
+#[cfg(not(unix))]
+pub async fn start(_idle_timeout_minutes: u64) -> Result<()> {
+    Err(anyhow!("The aaa agent is only supported on Unix"))
+}
+
+#[cfg(unix)]
+fn handle(request: Request, held: &mut Held, idle_timeout_minutes: u64) -> Response {
+    // Expired credentials are of no use to anyone, drop them as we go
+    held.sessions
+        .retain(|_, credentials| credentials.expiration > Utc::now());
+    held.tokens.retain(|_, token| {
+        token
+            .expiration()
+            .is_some_and(|expiration| expiration > Utc::now())
+    });
+
+    match request {
+        Request::Get { session } => Response::Credentials {
+            credentials: held.sessions.get(&session).cloned(),
+        },
+        Request::Store {
+            session,
+            credentials,
+        } => {
+            held.sessions.insert(session, credentials);
+            Response::Ok
+        }
+        Request::GetToken { start_url } => Response::Token {
+            token: held.tokens.get(&start_url).cloned(),
+        },
+        Request::StoreToken { start_url, token } => {
+            held.tokens.insert(start_url, token);
+            Response::Ok
+        }
+        Request::Status => {
+            let mut sessions: Vec<SessionInfo> = held
+                .sessions
+                .iter()
+                .map(|(session, credentials)| SessionInfo {
+                    session: session.clone(),
+                    expiration: credentials.expiration,
+                })
+                .collect();
+            sessions.sort_by(|a, b| a.session.profile.cmp(&b.session.profile));
+
+            let mut tokens: Vec<TokenInfo> = held
+                .tokens
+                .iter()
+                .map(|(start_url, token)| TokenInfo {
+                    start_url: start_url.clone(),
+                    expires_at: token.expires_at.clone(),
+                })
+                .collect();
+            tokens.sort_by(|a, b| a.start_url.cmp(&b.start_url));
+
+            Response::Status {
+                pid: std::process::id(),
+                idle_timeout_minutes,
+                sessions,
+                tokens,
+            }
+        }
+        Request::Lock => {
+            let cleared = Response::Cleared {
+                sessions: held.sessions.len(),
+                tokens: held.tokens.len(),
+            };
+            held.sessions.clear();
+            held.tokens.clear();
+            cleared
+        }
+        Request::Stop => Response::Ok,
+    }
+}
+
+// Serves requests until stopped, signalled or idle for idle_timeout_minutes (0 = never)
+#[cfg(unix)]
+pub async fn run(idle_timeout_minutes: u64) -> Result<()> {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::time::Instant;
+
+    let path = socket_path()?;
+
+    if send(&Request::Status).await.is_ok() {
+        return Err(anyhow!(
+            "An aaa agent is already listening on {}",
+            path.display()
+        ));
+    }
+
+    if let Some(directory) = path.parent() {
+        crate::session::create_private_dir(directory)?;
+    }
+
+    // A socket left behind by an agent that did not shut down cleanly
+    if path.exists() {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+        .context("Failed to restrict the agent socket permissions")?;
+
+    println!(
+        "{}",
+        format!("✓ aaa agent listening on {}", path.display()).green()
+    );
+
+    let idle_timeout = std::time::Duration::from_secs(idle_timeout_minutes * 60);
+    let mut last_activity = Instant::now();
+    let mut held = Held::default();
+    let mut terminate = signal(SignalKind::terminate()).context("Failed to handle SIGTERM")?;
+
+    loop {
+        let idle = tokio::time::sleep_until(last_activity + idle_timeout);
+
+        let mut stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => stream,
+                Err(_) => continue,
+            },
+            _ = idle, if idle_timeout_minutes > 0 => {
+                println!("{}", "aaa agent idle, shutting down".dimmed());
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => break,
+            _ = terminate.recv() => break,
+        };
+
+        last_activity = Instant::now();
+
+        let exchange = async {
+            let (reader, mut writer) = stream.split();
+            let mut line = String::new();
+            BufReader::new(reader).read_line(&mut line).await?;
+
+            let request: Request = serde_json::from_str(&line)?;
+            let stop = matches!(request, Request::Stop);
+            let response = handle(request, &mut held, idle_timeout_minutes);
+
+            writer.write_all(&serde_json::to_vec(&response)?).await?;
+            anyhow::Ok(stop)
+        };
+
+        // One slow or broken client must not hold up the others for long
+        match tokio::time::timeout(std::time::Duration::from_secs(5), exchange).await {
+            Ok(Ok(true)) => break,
+            _ => continue,
+        }
+    }
+
+    let _ = fs::remove_file(&path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub async fn run(_idle_timeout_minutes: u64) -> Result<()> {
+    Err(anyhow!("The aaa agent is only supported on Unix"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::okta::stand_in;
+    use crate::profile::{Profile, ProfileKind, ProfileSource};
+    use crate::test_support::TestEnv;
+
+    const START_URL: &str = "https://example.awsapps.com/start";
+
+    async fn start_agent() -> tokio::task::JoinHandle<Result<()>> {
+        let agent = tokio::spawn(run(0));
+        for _ in 0..50 {
+            if send(&Request::Status).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        agent
+    }
+
+    fn sso_token() -> SsoToken {
+        SsoToken {
+            start_url: START_URL.to_string(),
+            region: "eu-west-1".to_string(),
+            access_token: "access-token".to_string(),
+            expires_at: "2099-01-01T00:00:00Z".to_string(),
+            refresh_token: Some("refresh-token".to_string()),
+            client_id: None,
+            client_secret: None,
+            registration_expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn logins_stay_in_the_agent_until_it_is_locked() {
+        let mut test_env = TestEnv::new("agent-memory-only").await;
+        let okta = stand_in::start(&[]).await;
+        test_env.set("AWS_ENDPOINT_URL_STS", &okta.url);
+        let agent = start_agent().await;
+
+        let profile = Profile {
+            name: "okta-dev".to_string(),
+            kind: ProfileKind::Okta(stand_in::settings(&okta.url)),
+            region: Some("eu-west-1".to_string()),
+            extra: Vec::new(),
+            source: ProfileSource::Config,
+        };
+
+        let credentials = crate::session_credentials(&profile, false).await.unwrap();
+        assert_eq!(credentials.access_key_id(), "ASIAOKTA");
+        // The Okta session is not written to the credentials file
+        assert!(!test_env.home.join(".aws").join("credentials").exists());
+
+        // The next terminal gets it from the agent without logging in
+        let credentials = crate::session_credentials(&profile, false).await.unwrap();
+        assert_eq!(credentials.access_key_id(), "ASIAOKTA");
+        assert_eq!(okta.received_at("/").len(), 1);
+
+        assert!(store_token(START_URL, &sso_token()).await);
+        assert_eq!(
+            get_token(START_URL).await.unwrap().access_token,
+            "access-token"
+        );
+
+        lock().await.unwrap();
+        assert!(get(&profile.name).await.is_none());
+        assert!(get_token(START_URL).await.is_none());
+
+        stop().await.unwrap();
+        agent.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,396 @@
+use crate::loopback;
+use crate::profile::Profile;
+use crate::session;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::net::TcpListener;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const CREDENTIALS_PATH: &str = "/credentials";
+pub const FULL_URI_ENV: &str = "AWS_CONTAINER_CREDENTIALS_FULL_URI";
+pub const AUTHORIZATION_TOKEN_ENV: &str = "AWS_CONTAINER_AUTHORIZATION_TOKEN";
+// Served credentials without an expiry are handed out for this long at a time
+const STATIC_CREDENTIALS_LIFETIME_MINUTES: i64 = 60;
+// Retry delay after a failed background refresh
+const REFRESH_RETRY_SECONDS: u64 = 60;
+
+// Response format of the ECS container credentials provider; `aaa refresh`
+// sends new credentials to the endpoint in the same format
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    expiration: String,
+}
+
+impl ContainerCredentials {
+    fn new(credentials: &Credentials) -> ContainerCredentials {
+        let expiration = expires_at(credentials)
+            .unwrap_or_else(|| Utc::now() + Duration::minutes(STATIC_CREDENTIALS_LIFETIME_MINUTES));
+
+        ContainerCredentials {
+            access_key_id: credentials.access_key_id().to_string(),
+            secret_access_key: credentials.secret_access_key().to_string(),
+            token: credentials.session_token().map(String::from),
+            expiration: expiration.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        }
+    }
+
+    fn into_credentials(self) -> Result<Credentials> {
+        let expiration = DateTime::parse_from_rfc3339(&self.expiration)
+            .context("Invalid credential expiration")?;
+
+        Ok(Credentials::new(
+            self.access_key_id,
+            self.secret_access_key,
+            self.token,
+            Some(SystemTime::from(expiration)),
+            "aaa-credentials-endpoint",
+        ))
+    }
+}
+
+// Loopback endpoint serving a profile's credentials to SDKs in the subshell;
+// it stops when dropped
+pub struct CredentialsEndpoint {
+    pub full_uri: String,
+    pub authorization_token: String,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Drop for CredentialsEndpoint {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+fn expires_at(credentials: &Credentials) -> Option<DateTime<Utc>> {
+    credentials.expiry().map(DateTime::<Utc>::from)
+}
+
+fn refresh_at(credentials: &Credentials) -> Option<DateTime<Utc>> {
+    expires_at(credentials)
+        .map(|expires_at| expires_at - Duration::minutes(crate::SESSION_REFRESH_MARGIN_MINUTES))
+}
+
+fn needs_refresh(credentials: &Credentials) -> bool {
+    refresh_at(credentials).is_some_and(|refresh_at| refresh_at <= Utc::now())
+}
+
+// The endpoint runs in the aaa process of the shell it serves, so that
+// session's file is the one prompts and `aaa refresh` read the expiry from
+fn record_expiration(credentials: &Credentials) {
+    let Ok(path) = session::state_path(std::process::id()) else {
+        return;
+    };
+    if let Ok(mut state) = session::load(&path) {
+        state.expiration = expires_at(credentials);
+        let _ = session::save(&path, &state);
+    }
+}
+
+fn replace_credentials(credentials: &mut Credentials, new_credentials: Credentials) {
+    record_expiration(&new_credentials);
+    *credentials = new_credentials;
+}
+
+// Takes a newer session from the aaa agent or the profile's cached login; this
+// runs in the background, so it never prompts. Sessions that need a login are
+// renewed by `aaa refresh`, which sends the new credentials here
+async fn refresh(profile: &Profile, credentials: &mut Credentials) -> Result<()> {
+    let new_credentials = crate::unattended_session_credentials(profile).await?;
+    replace_credentials(credentials, new_credentials);
+    Ok(())
+}
+
+async fn current_credentials(
+    profile: &Profile,
+    state: &Mutex<Credentials>,
+) -> Result<ContainerCredentials> {
+    let mut credentials = state.lock().await;
+    if needs_refresh(&credentials) {
+        refresh(profile, &mut credentials).await?;
+    }
+
+    Ok(ContainerCredentials::new(&credentials))
+}
+
+async fn handle(
+    request: &loopback::HttpRequest,
+    authorization_token: &str,
+    profile: &Profile,
+    state: &Mutex<Credentials>,
+) -> (&'static str, String) {
+    let message = |message: &str| serde_json::json!({ "message": message }).to_string();
+
+    if request.path != CREDENTIALS_PATH {
+        return ("404 Not Found", message("Not found"));
+    }
+    if request.header("Authorization") != Some(authorization_token) {
+        return ("401 Unauthorized", message("Unauthorized"));
+    }
+
+    match request.method.as_str() {
+        "GET" => match current_credentials(profile, state).await {
+            Ok(credentials) => (
+                "200 OK",
+                serde_json::to_string(&credentials).unwrap_or_default(),
+            ),
+            Err(e) => ("500 Internal Server Error", message(&format!("{:#}", e))),
+        },
+        "PUT" => {
+            let new_credentials = serde_json::from_str::<ContainerCredentials>(&request.body)
+                .context("Invalid credentials")
+                .and_then(ContainerCredentials::into_credentials);
+            match new_credentials {
+                Ok(new_credentials) => {
+                    replace_credentials(&mut *state.lock().await, new_credentials);
+                    ("200 OK", message("Credentials replaced"))
+                }
+                Err(e) => ("400 Bad Request", message(&format!("{:#}", e))),
+            }
+        }
+        _ => ("405 Method Not Allowed", message("Method not allowed")),
+    }
+}
+
+async fn serve(
+    listener: TcpListener,
+    authorization_token: String,
+    profile: Arc<Profile>,
+    state: Arc<Mutex<Credentials>>,
+) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+
+        let authorization_token = authorization_token.clone();
+        let profile = profile.clone();
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let Ok(request) = loopback::read_request(&mut stream).await else {
+                return;
+            };
+
+            let (status, body) = handle(&request, &authorization_token, &profile, &state).await;
+
+            let _ = loopback::write_response(&mut stream, status, loopback::JSON, &body).await;
+        });
+    }
+}
+
+// Refreshes ahead of time so SDK requests never wait on a renewal
+async fn refresh_before_expiry(profile: Arc<Profile>, state: Arc<Mutex<Credentials>>) {
+    loop {
+        let Some(refresh_at) = refresh_at(&*state.lock().await) else {
+            return;
+        };
+
+        let wait = (refresh_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+
+        let mut credentials = state.lock().await;
+        if !needs_refresh(&credentials) {
+            continue;
+        }
+
+        println!();
+        println!(
+            "{}",
+            format!("aaa: refreshing credentials for '{}'...", profile.name).yellow()
+        );
+
+        match refresh(&profile, &mut credentials).await {
+            Ok(()) => println!("{}", "aaa: ✓ credentials refreshed".green()),
+            Err(e) => {
+                println!("{} {:#}", "aaa: failed to refresh credentials:".red(), e);
+                drop(credentials);
+                tokio::time::sleep(std::time::Duration::from_secs(REFRESH_RETRY_SECONDS)).await;
+            }
+        }
+    }
+}
+
+pub async fn start(profile: &Profile, credentials: Credentials) -> Result<CredentialsEndpoint> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("Failed to start the local credentials endpoint")?;
+    let address = listener
+        .local_addr()
+        .context("Failed to start the local credentials endpoint")?;
+
+    let authorization_token = loopback::random_token()?;
+    let profile = Arc::new(profile.clone());
+    let state = Arc::new(Mutex::new(credentials));
+
+    let tasks = vec![
+        tokio::spawn(serve(
+            listener,
+            authorization_token.clone(),
+            profile.clone(),
+            state.clone(),
+        )),
+        tokio::spawn(refresh_before_expiry(profile, state)),
+    ];
+
+    Ok(CredentialsEndpoint {
+        full_uri: format!("http://{}{}", address, CREDENTIALS_PATH),
+        authorization_token,
+        tasks,
+    })
+}
+
+// Hands the credentials of a new login to the endpoint serving the shell that
+// `aaa refresh` runs in
+pub async fn replace(full_uri: &str, credentials: &Credentials) -> Result<()> {
+    let authorization_token = std::env::var(AUTHORIZATION_TOKEN_ENV)
+        .with_context(|| format!("{} is not set", AUTHORIZATION_TOKEN_ENV))?;
+
+    let response = reqwest::Client::new()
+        .put(full_uri)
+        .header("Authorization", authorization_token)
+        .json(&ContainerCredentials::new(credentials))
+        .send()
+        .await
+        .context("Failed to reach the shell's credentials endpoint")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "The shell's credentials endpoint rejected the new credentials with HTTP {}: {}",
+            status,
+            message
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::okta::stand_in;
+    use crate::profile::{ProfileKind, ProfileSource};
+    use crate::test_support::TestEnv;
+
+    // A session a few minutes from expiry, inside the refresh margin
+    fn expiring_credentials() -> Credentials {
+        Credentials::new(
+            "ASIAEXPIRING",
+            "secret",
+            Some("token".to_string()),
+            Some(SystemTime::now() + std::time::Duration::from_secs(5 * 60)),
+            "test",
+        )
+    }
+
+    async fn get_credentials(endpoint: &CredentialsEndpoint) -> (u16, serde_json::Value) {
+        let response = reqwest::Client::new()
+            .get(&endpoint.full_uri)
+            .header("Authorization", &endpoint.authorization_token)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status().as_u16();
+        (status, response.json().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn refresh_hands_a_new_okta_session_to_the_endpoint() {
+        let mut test_env = TestEnv::new("endpoint-refresh").await;
+        let home = test_env.home.clone();
+        test_env.set("XDG_RUNTIME_DIR", &home);
+        let okta = stand_in::start(&[]).await;
+        test_env.set("AWS_ENDPOINT_URL_STS", &okta.url);
+
+        let profile = Profile {
+            name: "okta-dev".to_string(),
+            kind: ProfileKind::Okta(stand_in::settings(&okta.url)),
+            region: Some("eu-west-1".to_string()),
+            extra: Vec::new(),
+            source: ProfileSource::Config,
+        };
+
+        // What `aaa --credentials-endpoint` sets up for the shell
+        let credentials = expiring_credentials();
+        let expiration = expires_at(&credentials);
+        let endpoint = start(&profile, credentials).await.unwrap();
+        let session_files = session::create(&session::SessionState {
+            profile: profile.name.clone(),
+            region: profile.region.clone(),
+            expiration,
+            pid: std::process::id(),
+            parent: None,
+            depth: 1,
+            shell_pid: None,
+            closed: false,
+            credentials_endpoint: Some(endpoint.full_uri.clone()),
+        })
+        .unwrap();
+        test_env.set(session::STATE_FILE_ENV, &session_files.state_path);
+        test_env.set(session::ENV_FILE_ENV, &session_files.env_path);
+        test_env.set(AUTHORIZATION_TOKEN_ENV, &endpoint.authorization_token);
+
+        // An Okta session cannot be renewed without logging in again
+        let (status, body) = get_credentials(&endpoint).await;
+        assert_eq!(status, 500);
+        assert!(body["message"].as_str().unwrap().contains("aaa refresh"));
+
+        crate::refresh_session(std::slice::from_ref(&profile))
+            .await
+            .unwrap();
+
+        // The device flow and AssumeRoleWithSAML ran against the stand-in
+        assert_eq!(okta.received_at("/login/token/sso").len(), 1);
+        assert_eq!(okta.received_at("/").len(), 1);
+
+        let (status, body) = get_credentials(&endpoint).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["AccessKeyId"], "ASIAOKTA");
+        assert_eq!(body["Token"], "session-token");
+        assert_eq!(body["Expiration"], "2030-01-01T00:00:00Z");
+
+        // Prompts see the new expiry; the env file is not used
+        let state = session::load(&session_files.state_path).unwrap();
+        assert_eq!(
+            state.expiration.map(|expiration| expiration.to_rfc3339()),
+            Some("2030-01-01T00:00:00+00:00".to_string())
+        );
+        assert!(!session_files.env_path.exists());
+    }
+
+    #[tokio::test]
+    async fn replacing_credentials_needs_the_authorization_token() {
+        let mut test_env = TestEnv::new("endpoint-replace").await;
+        let home = test_env.home.clone();
+        test_env.set("XDG_RUNTIME_DIR", &home);
+
+        let profile = Profile {
+            name: "static".to_string(),
+            kind: ProfileKind::Static { mfa_serial: None },
+            region: None,
+            extra: Vec::new(),
+            source: ProfileSource::Credentials,
+        };
+        let endpoint = start(&profile, expiring_credentials()).await.unwrap();
+        let forged = Credentials::new("AKIAFORGED", "secret", None, None, "test");
+
+        test_env.set(AUTHORIZATION_TOKEN_ENV, "guessed");
+        let error = replace(&endpoint.full_uri, &forged).await.unwrap_err();
+        assert!(error.to_string().contains("HTTP 401"));
+    }
+}
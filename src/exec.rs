@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+// Signals aaa passes on to the command instead of acting on them itself
+#[cfg(unix)]
+const FORWARDED_SIGNALS: [libc::c_int; 2] = [libc::SIGTERM, libc::SIGHUP];
+// The terminal sends these to the whole foreground process group, so the
+// command already gets them; forwarding would deliver them twice. aaa only has
+// to survive them, with a handler rather than SIG_IGN, which the command would
+// inherit
+#[cfg(unix)]
+const TERMINAL_SIGNALS: [libc::c_int; 2] = [libc::SIGINT, libc::SIGQUIT];
+
+// Runs the command to completion, forwarding signals to it, and returns the
+// exit code aaa should exit with
+#[cfg(unix)]
+pub async fn run(command: Command) -> Result<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let program = command.get_program().to_string_lossy().to_string();
+
+    // Listen before spawning so no signal falls through to the default handler
+    let mut listeners = Vec::new();
+    for (number, forward) in FORWARDED_SIGNALS
+        .map(|number| (number, true))
+        .into_iter()
+        .chain(TERMINAL_SIGNALS.map(|number| (number, false)))
+    {
+        let listener = signal(SignalKind::from_raw(number))
+            .with_context(|| format!("Failed to handle signal {}", number))?;
+        listeners.push((number, forward, listener));
+    }
+
+    let mut child = tokio::process::Command::from(command)
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", program))?;
+    let pid = child
+        .id()
+        .ok_or_else(|| anyhow!("'{}' exited before it could be tracked", program))?
+        as libc::pid_t;
+
+    let forwarders: Vec<_> = listeners
+        .into_iter()
+        .map(|(number, forward, mut listener)| {
+            tokio::spawn(async move {
+                while listener.recv().await.is_some() {
+                    if forward {
+                        // SAFETY: sends a signal to our own child process
+                        unsafe { libc::kill(pid, number) };
+                    }
+                }
+            })
+        })
+        .collect();
+
+    let status = child.wait().await;
+
+    for forwarder in forwarders {
+        forwarder.abort();
+    }
+
+    let status = status.with_context(|| format!("Failed to wait for '{}'", program))?;
+
+    // Shells report death by signal as 128 + the signal number
+    Ok(status
+        .code()
+        .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)))
+}
+
+// Ctrl+C reaches the whole console, so aaa only has to survive it
+#[cfg(not(unix))]
+pub async fn run(command: Command) -> Result<i32> {
+    let program = command.get_program().to_string_lossy().to_string();
+
+    let mut child = tokio::process::Command::from(command)
+        .spawn()
+        .with_context(|| format!("Failed to run '{}'", program))?;
+
+    let status = loop {
+        tokio::select! {
+            status = child.wait() => break status,
+            _ = tokio::signal::ctrl_c() => continue,
+        }
+    };
+
+    let status = status.with_context(|| format!("Failed to wait for '{}'", program))?;
+    Ok(status.code().unwrap_or(1))
+}
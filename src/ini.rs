@@ -0,0 +1,618 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// An INI file as the AWS CLI reads it, kept line by line so that comments,
+// ordering, spacing and line endings survive a round trip. Only lines that
+// are changed are rewritten.
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    // Raw text including the line ending
+    text: String,
+    kind: LineKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LineKind {
+    // Blank lines, comments and anything aaa does not understand
+    Other,
+    Section(String),
+    // Values continued on indented lines hold them after a newline each, like
+    // in Python's configparser
+    Property { key: String, value: String },
+    // Indented line continuing the property above, e.g. the keys of a nested
+    // `s3 =` sub-section
+    Continuation,
+}
+
+impl Document {
+    pub fn parse(content: &str) -> Document {
+        let mut lines: Vec<Line> = Vec::new();
+        let mut in_section = false;
+        // Indent of the key line whose value deeper indented lines continue
+        let mut property_indent: Option<usize> = None;
+        let mut property_index = 0;
+
+        for text in content.split_inclusive('\n') {
+            let body = text.trim_end_matches(['\r', '\n']);
+            let trimmed = body.trim();
+            let continues = property_indent.is_some_and(|indent| indent_of(body) > indent);
+            let is_comment = trimmed.starts_with('#') || trimmed.starts_with(';');
+
+            let kind = if trimmed.is_empty() {
+                property_indent = None;
+                LineKind::Other
+            } else if continues && is_comment {
+                // Belongs to the nested block, so it goes wherever the block goes
+                LineKind::Continuation
+            } else if is_comment {
+                LineKind::Other
+            } else if continues {
+                if let LineKind::Property { value, .. } = &mut lines[property_index].kind {
+                    value.push('\n');
+                    value.push_str(trimmed);
+                }
+                LineKind::Continuation
+            } else if let Some(name) = section_header(trimmed) {
+                in_section = true;
+                property_indent = None;
+                LineKind::Section(name.to_string())
+            } else if let (true, Some(separator)) = (in_section, trimmed.find(['=', ':'])) {
+                property_indent = Some(indent_of(body));
+                property_index = lines.len();
+                LineKind::Property {
+                    key: trimmed[..separator].trim().to_string(),
+                    value: trimmed[separator + 1..].trim().to_string(),
+                }
+            } else {
+                LineKind::Other
+            };
+
+            lines.push(Line {
+                text: text.to_string(),
+                kind,
+            });
+        }
+
+        Document { lines }
+    }
+
+    // A missing file reads as an empty document
+    pub fn load(path: &Path) -> Result<Document> {
+        if !path.exists() {
+            return Ok(Document::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Document::parse(&content))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+
+        fs::write(path, self.to_string())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    // Section names in file order, each listed once
+    pub fn section_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = Vec::new();
+        for line in &self.lines {
+            if let LineKind::Section(name) = &line.kind {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+
+    pub fn has_section(&self, name: &str) -> bool {
+        !self.spans(name).is_empty()
+    }
+
+    // Keys and values of a section; a section that appears more than once is
+    // merged, and later values win like in the AWS CLI
+    pub fn section(&self, name: &str) -> Option<HashMap<String, String>> {
+        if !self.has_section(name) {
+            return None;
+        }
+        Some(self.entries(name).into_iter().collect())
+    }
+
+    // Keys and values of a section in file order, duplicates included
+    pub fn entries(&self, name: &str) -> Vec<(String, String)> {
+        self.spans(name)
+            .into_iter()
+            .flat_map(|(start, end)| &self.lines[start + 1..end])
+            .filter_map(|line| match &line.kind {
+                LineKind::Property { key, value } => Some((key.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.property_lines(section, key)
+            .last()
+            .and_then(|&index| match &self.lines[index].kind {
+                LineKind::Property { value, .. } => Some(value.as_str()),
+                _ => None,
+            })
+    }
+
+    // Updates the key in place, or adds it at the end of the section, creating
+    // the section at the end of the file when needed; an unchanged value
+    // leaves the file untouched
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        let mut value_lines = value.split('\n');
+        let first_line = value_lines.next().unwrap_or_default();
+        let newline = self.newline();
+        // Continuations must be indented deeper than the key line
+        let key_indent = match self.property_lines(section, key).last() {
+            Some(&index) => leading_whitespace(&self.lines[index].text),
+            None => "",
+        };
+        let continuations: Vec<String> = value_lines
+            .map(|line| format!("{}  {}{}", key_indent, line, newline))
+            .collect();
+        let property = LineKind::Property {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        let index = if let Some(&index) = self.property_lines(section, key).last() {
+            if self.get(section, key) == Some(value) {
+                return;
+            }
+            self.remove_continuations(index);
+
+            let line = &mut self.lines[index];
+            let body = line.text.trim_end_matches(['\r', '\n']);
+            let ending = line.text[body.len()..].to_string();
+            let separator = body.find(['=', ':']).unwrap_or(body.len());
+            let spacing = &body[separator + 1..];
+            let spacing = &spacing[..spacing.len() - spacing.trim_start().len()];
+            let spacing = match (first_line.is_empty(), spacing.is_empty()) {
+                (true, _) => "",
+                (false, true) => " ",
+                (false, false) => spacing,
+            };
+
+            line.text = format!("{}{}{}{}", &body[..=separator], spacing, first_line, ending);
+            line.kind = property;
+            index
+        } else {
+            let text = if first_line.is_empty() {
+                format!("{} ={}", key, newline)
+            } else {
+                format!("{} = {}{}", key, first_line, newline)
+            };
+
+            let index = match self.spans(section).last() {
+                Some(&(start, end)) => self.content_end(start, end),
+                None => {
+                    self.append_section(section);
+                    self.lines.len()
+                }
+            };
+            self.insert(index, text, property);
+            index
+        };
+
+        for (offset, text) in continuations.into_iter().enumerate() {
+            self.insert(index + 1 + offset, text, LineKind::Continuation);
+        }
+    }
+
+    // Removes every occurrence of the key; returns whether there was one
+    pub fn remove(&mut self, section: &str, key: &str) -> bool {
+        let indexes = self.property_lines(section, key);
+        for &index in indexes.iter().rev() {
+            self.remove_continuations(index);
+            self.lines.remove(index);
+        }
+        !indexes.is_empty()
+    }
+
+    // Removes the section with its keys, the comments right above its header
+    // and the blank lines after it; comments between its last key and the next
+    // section stay, as they usually describe the next one
+    pub fn remove_section(&mut self, name: &str) -> bool {
+        let spans = self.spans(name);
+        for &(start, end) in spans.iter().rev() {
+            // Removing a later occurrence may have taken the blank lines this one
+            // ended on
+            let mut end = self.content_end(start, end.min(self.lines.len()));
+            let mut start = start;
+            while start > 0 && self.is_comment(start - 1) {
+                start -= 1;
+            }
+            while end < self.lines.len() && self.lines[end].text.trim().is_empty() {
+                end += 1;
+            }
+            if end == self.lines.len() {
+                while start > 0 && self.lines[start - 1].text.trim().is_empty() {
+                    start -= 1;
+                }
+            }
+            self.lines.drain(start..end);
+        }
+        !spans.is_empty()
+    }
+
+    pub fn rename_section(&mut self, name: &str, new_name: &str) -> bool {
+        let mut renamed = false;
+        for line in &mut self.lines {
+            if line.kind == LineKind::Section(name.to_string()) {
+                // Keeps a trailing comment after the header
+                let rest = &line.text[line.text.find(']').map_or(0, |index| index + 1)..];
+                line.text = format!("[{}]{}", new_name, rest);
+                line.kind = LineKind::Section(new_name.to_string());
+                renamed = true;
+            }
+        }
+        renamed
+    }
+
+    // Appends a copy of the section under a new name, keeping its keys, nested
+    // sub-sections and comments as written
+    pub fn copy_section(&mut self, name: &str, new_name: &str) -> bool {
+        let copied: Vec<Line> = self
+            .spans(name)
+            .into_iter()
+            .flat_map(|(start, end)| self.lines[start + 1..self.content_end(start, end)].to_vec())
+            .collect();
+        if copied.is_empty() && !self.has_section(name) {
+            return false;
+        }
+
+        self.append_section(new_name);
+        for line in copied {
+            self.insert(self.lines.len(), line.text, line.kind);
+        }
+        true
+    }
+
+    // Starts a new section at the end of the file, separated by a blank line
+    fn append_section(&mut self, name: &str) {
+        if self
+            .lines
+            .last()
+            .is_some_and(|line| !line.text.trim().is_empty())
+        {
+            self.insert(
+                self.lines.len(),
+                self.newline().to_string(),
+                LineKind::Other,
+            );
+        }
+        self.insert(
+            self.lines.len(),
+            format!("[{}]{}", name, self.newline()),
+            LineKind::Section(name.to_string()),
+        );
+    }
+
+    // (header, end) index ranges of every occurrence of the section
+    fn spans(&self, name: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            if let LineKind::Section(section) = &line.kind {
+                if let Some(span) = spans.last_mut().filter(|(_, end)| *end == usize::MAX) {
+                    span.1 = index;
+                }
+                if section == name {
+                    spans.push((index, usize::MAX));
+                }
+            }
+        }
+        if let Some(span) = spans.last_mut().filter(|(_, end)| *end == usize::MAX) {
+            span.1 = self.lines.len();
+        }
+        spans
+    }
+
+    // Index after the last key of the section, before trailing blank lines
+    // and comments
+    fn content_end(&self, start: usize, end: usize) -> usize {
+        (start + 1..end)
+            .rev()
+            .find(|&index| {
+                matches!(
+                    self.lines[index].kind,
+                    LineKind::Property { .. } | LineKind::Continuation
+                )
+            })
+            .map_or(start + 1, |index| index + 1)
+    }
+
+    fn is_comment(&self, index: usize) -> bool {
+        let line = &self.lines[index];
+        let trimmed = line.text.trim_start();
+        line.kind == LineKind::Other && (trimmed.starts_with('#') || trimmed.starts_with(';'))
+    }
+
+    fn property_lines(&self, section: &str, key: &str) -> Vec<usize> {
+        self.spans(section)
+            .into_iter()
+            .flat_map(|(start, end)| start + 1..end)
+            .filter(|&index| {
+                matches!(&self.lines[index].kind, LineKind::Property { key: k, .. } if k == key)
+            })
+            .collect()
+    }
+
+    fn remove_continuations(&mut self, index: usize) {
+        let count = self.lines[index + 1..]
+            .iter()
+            .take_while(|line| line.kind == LineKind::Continuation)
+            .count();
+        self.lines.drain(index + 1..index + 1 + count);
+    }
+
+    fn insert(&mut self, index: usize, text: String, kind: LineKind) {
+        if index > 0 && !self.lines[index - 1].text.ends_with('\n') {
+            let newline = self.newline();
+            self.lines[index - 1].text.push_str(newline);
+        }
+        self.lines.insert(index, Line { text, kind });
+    }
+
+    // New lines follow the file's existing line endings
+    fn newline(&self) -> &'static str {
+        match self.lines.first() {
+            Some(line) if line.text.ends_with("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+// Name of a `[section]` header, which may be followed by a comment
+fn section_header(trimmed: &str) -> Option<&str> {
+    let (name, rest) = trimmed.strip_prefix('[')?.split_once(']')?;
+    let rest = rest.trim_start();
+    (rest.is_empty() || rest.starts_with('#') || rest.starts_with(';')).then(|| name.trim())
+}
+
+fn leading_whitespace(text: &str) -> &str {
+    &text[..text.len() - text.trim_start().len()]
+}
+
+fn indent_of(text: &str) -> usize {
+    leading_whitespace(text).len()
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Managed by hand
+[default]
+region = eu-west-1
+
+; the main account
+[profile dev] # sandbox
+region=us-east-1
+s3 =
+  max_concurrent_requests = 10
+  # tuned for uploads
+  addressing_style = path
+output = json
+
+[profile dev]
+region = eu-central-1
+";
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        let crlf = CONFIG.replace('\n', "\r\n");
+        let unterminated = CONFIG.trim_end();
+        for content in [CONFIG, crlf.as_str(), unterminated, "", "\n\n"] {
+            assert_eq!(Document::parse(content).to_string(), content);
+        }
+    }
+
+    #[test]
+    fn parse_reads_headers_nested_blocks_and_duplicates() {
+        let document = Document::parse(CONFIG);
+
+        assert_eq!(document.section_names(), ["default", "profile dev"]);
+        assert_eq!(
+            document.entries("profile dev"),
+            pairs(&[
+                ("region", "us-east-1"),
+                (
+                    "s3",
+                    "\nmax_concurrent_requests = 10\naddressing_style = path"
+                ),
+                ("output", "json"),
+                ("region", "eu-central-1"),
+            ])
+        );
+        // Later duplicates win
+        assert_eq!(document.get("profile dev", "region"), Some("eu-central-1"));
+        assert_eq!(document.get("profile dev", "max_concurrent_requests"), None);
+    }
+
+    #[test]
+    fn continuations_must_be_indented_deeper_than_their_key() {
+        let document = Document::parse(
+            "[default]\n  s3 =\n    addressing_style = path\n  region = eu-west-1\n",
+        );
+
+        assert_eq!(
+            document.entries("default"),
+            pairs(&[("s3", "\naddressing_style = path"), ("region", "eu-west-1")])
+        );
+    }
+
+    #[test]
+    fn set_updates_in_place_keeping_the_layout() {
+        let mut document = Document::parse(&CONFIG.replace('\n', "\r\n"));
+
+        document.set("default", "region", "us-west-2");
+        document.set("profile dev", "output", "json");
+
+        assert_eq!(
+            document.to_string(),
+            CONFIG
+                .replace("region = eu-west-1", "region = us-west-2")
+                .replace('\n', "\r\n")
+        );
+    }
+
+    #[test]
+    fn set_replaces_a_nested_block_with_its_comments() {
+        let mut document = Document::parse(CONFIG);
+
+        document.set("profile dev", "s3", "\naddressing_style = virtual");
+
+        assert_eq!(
+            document.to_string(),
+            CONFIG.replace(
+                "  max_concurrent_requests = 10\n  # tuned for uploads\n  addressing_style = path\n",
+                "  addressing_style = virtual\n"
+            )
+        );
+        assert_eq!(
+            document.get("profile dev", "s3"),
+            Some("\naddressing_style = virtual")
+        );
+    }
+
+    #[test]
+    fn set_appends_to_the_section_before_trailing_blank_lines() {
+        let mut document = Document::parse(CONFIG);
+
+        document.set("default", "output", "text");
+
+        assert_eq!(
+            document.to_string(),
+            CONFIG.replace(
+                "region = eu-west-1\n",
+                "region = eu-west-1\noutput = text\n"
+            )
+        );
+    }
+
+    #[test]
+    fn set_creates_a_missing_section_at_the_end() {
+        let mut document = Document::parse("[default]\r\nregion = eu-west-1");
+
+        document.set("profile ops", "role_arn", "arn:aws:iam::123:role/Ops");
+        document.set("profile ops", "s3", "\naddressing_style = path");
+
+        assert_eq!(
+            document.to_string(),
+            "[default]\r\nregion = eu-west-1\r\n\r\n[profile ops]\r\n\
+             role_arn = arn:aws:iam::123:role/Ops\r\ns3 =\r\n  addressing_style = path\r\n"
+        );
+    }
+
+    #[test]
+    fn remove_drops_every_occurrence_and_nested_block() {
+        let mut document = Document::parse(CONFIG);
+
+        assert!(document.remove("profile dev", "region"));
+        assert!(document.remove("profile dev", "s3"));
+        assert!(!document.remove("profile dev", "s3"));
+
+        assert_eq!(
+            document.to_string(),
+            "\
+# Managed by hand
+[default]
+region = eu-west-1
+
+; the main account
+[profile dev] # sandbox
+output = json
+
+[profile dev]
+"
+        );
+    }
+
+    #[test]
+    fn remove_section_takes_the_comments_above_it() {
+        let mut document = Document::parse(CONFIG);
+
+        assert!(document.remove_section("profile dev"));
+        assert!(!document.remove_section("profile dev"));
+
+        assert_eq!(
+            document.to_string(),
+            "# Managed by hand\n[default]\nregion = eu-west-1\n"
+        );
+    }
+
+    #[test]
+    fn remove_section_leaves_comments_about_the_next_section() {
+        let mut document = Document::parse(CONFIG);
+
+        assert!(document.remove_section("default"));
+
+        assert!(document
+            .to_string()
+            .starts_with("; the main account\n[profile dev]"));
+    }
+
+    #[test]
+    fn rename_section_keeps_keys_and_header_comments() {
+        let mut document = Document::parse(CONFIG);
+
+        assert!(document.rename_section("profile dev", "profile sandbox"));
+        assert!(!document.rename_section("profile missing", "profile other"));
+
+        assert_eq!(
+            document.to_string(),
+            CONFIG.replace("[profile dev]", "[profile sandbox]")
+        );
+    }
+
+    #[test]
+    fn copy_section_appends_every_occurrence_under_the_new_name() {
+        let mut document = Document::parse(CONFIG);
+
+        assert!(document.copy_section("profile dev", "profile copy"));
+        assert!(!document.copy_section("profile missing", "profile other"));
+
+        assert_eq!(
+            document.entries("profile copy"),
+            document.entries("profile dev")
+        );
+        assert!(document.to_string().ends_with(
+            "\n\n[profile copy]\nregion=us-east-1\ns3 =\n  max_concurrent_requests = 10\n  \
+             # tuned for uploads\n  addressing_style = path\noutput = json\nregion = eu-central-1\n"
+        ));
+    }
+}
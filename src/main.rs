@@ -1,29 +1,235 @@
 use anyhow::{anyhow, Context, Result};
 use aws_credential_types::provider::ProvideCredentials;
-use clap::Parser;
+use aws_credential_types::Credentials;
+use clap::{Parser, Subcommand};
 use colored::Colorize;
-use inquire::{Select, Text};
+use inquire::{Confirm, MultiSelect, Select, Text};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
-use std::io::Write;
 use std::path::PathBuf;
 use std::process::Command;
 
+mod agent;
+mod credentials_endpoint;
+mod exec;
+mod ini;
+mod loopback;
+mod okta;
+mod output;
+mod profile;
+mod roles_anywhere;
+mod saml;
+mod session;
+mod shell;
+mod sso;
+mod sts;
+#[cfg(test)]
+mod test_support;
+mod web_identity;
+
+use profile::{
+    AssumeRoleSettings, OktaSettings, Profile, ProfileKind, ProfileSource, RolesAnywhereSettings,
+    SamlSettings, SsoSession, SsoSettings, WebIdentitySettings,
+};
+
 #[derive(Parser)]
 #[command(name = "aaa")]
 #[command(about = "AWS Account Alternator - Manage AWS profiles and SSO authentication")]
 #[command(version)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// Profile name to use (if not specified, shows interactive menu)
     profile: Option<String>,
+
+    /// Log in again even if the cached SSO token or session credentials are still valid
+    #[arg(long)]
+    force_login: bool,
+
+    /// Serve credentials to the shell from a local endpoint that refreshes them before they expire
+    #[arg(long)]
+    credentials_endpoint: bool,
+
+    /// Warn in the shell this many minutes before its credentials expire
+    #[arg(long, value_name = "MINUTES")]
+    warn_before: Option<u64>,
+
+    /// What to do when started inside another aaa session, instead of asking
+    #[arg(long, value_enum, value_name = "ACTION")]
+    nested: Option<NestedAction>,
+
+    /// AWS config file to use instead of ~/.aws/config [env: AWS_CONFIG_FILE]
+    #[arg(long, global = true, value_name = "PATH")]
+    config_file: Option<PathBuf>,
+
+    /// AWS credentials file to use instead of ~/.aws/credentials [env: AWS_SHARED_CREDENTIALS_FILE]
+    #[arg(long, global = true, value_name = "PATH")]
+    credentials_file: Option<PathBuf>,
+
+    /// Okta config file to use instead of ~/.okta/okta.yaml [env: AAA_OKTA_CONFIG]
+    #[arg(long, global = true, value_name = "PATH")]
+    okta_config: Option<PathBuf>,
+}
+
+// How a profile's session is started, from the command-line flags
+#[derive(Debug, Clone, Copy, Default)]
+struct SessionOptions {
+    force_login: bool,
+    credentials_endpoint: bool,
+    warn_before: Option<u64>,
+    nested: Option<NestedAction>,
+}
+
+// What to do when a session shell is started from inside another one
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum NestedAction {
+    /// Start the new session inside the current one and close the current one
+    /// when the new one exits
+    CloseOuter,
+    /// Start the new session inside the current one
+    Nest,
+    /// Do nothing
+    Abort,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Print credentials for a profile in the credential_process JSON format
+    CredentialProcess {
+        /// Profile name to get credentials for
+        profile: String,
+
+        /// Write a profile with this name to the AWS config file that runs this command instead
+        #[arg(long, value_name = "NAME")]
+        install: Option<String>,
+    },
+    /// Change a profile's settings, interactively or with --set and --unset
+    Edit {
+        /// Profile name to edit
+        profile: String,
+
+        /// Set a setting, as KEY=VALUE (can be repeated)
+        #[arg(long, value_name = "KEY=VALUE")]
+        set: Vec<String>,
+
+        /// Remove a setting (can be repeated)
+        #[arg(long, value_name = "KEY")]
+        unset: Vec<String>,
+    },
+    /// Delete a profile from the config, credentials and okta.yaml files
+    Rm {
+        /// Profile name to delete
+        profile: String,
+
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Rename a profile
+    Mv {
+        /// Profile name to rename
+        profile: String,
+
+        /// New profile name
+        new_name: String,
+    },
+    /// Copy a profile under a new name
+    Cp {
+        /// Profile name to copy
+        profile: String,
+
+        /// Name of the copy
+        new_name: String,
+    },
+    /// Discover SSO accounts and roles and generate profiles for them
+    SsoSync {
+        /// Profile name template ({account_name}, {account_id} and {role_name} are replaced)
+        #[arg(long, default_value = DEFAULT_PROFILE_NAME_TEMPLATE)]
+        template: String,
+    },
+    /// Run a command with a profile's credentials and exit with its exit code
+    Exec {
+        /// Profile name to get credentials for
+        profile: String,
+
+        /// Log in again even if the cached SSO token or session credentials are still valid
+        #[arg(long)]
+        force_login: bool,
+
+        /// Command to run, after --
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Print a profile's credentials as statements for the current shell
+    Env {
+        /// Profile name to get credentials for
+        #[arg(required_unless_present = "unset")]
+        profile: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = shell::EnvFormat::Bash)]
+        format: shell::EnvFormat,
+
+        /// Print statements that clear the variables aaa manages instead
+        #[arg(long, conflicts_with = "profile")]
+        unset: bool,
+
+        /// Log in again even if the cached SSO token or session credentials are still valid
+        #[arg(long)]
+        force_login: bool,
+    },
+    /// List the chain of aaa sessions the current shell runs in
+    Sessions,
+    /// Renew the credentials of the current aaa shell in place
+    Refresh,
+    /// Print a prompt segment for the current aaa shell (nothing outside of one)
+    Prompt {
+        /// Segment template ({profile}, {region} and {remaining} are replaced)
+        #[arg(long, default_value = DEFAULT_PROMPT_TEMPLATE)]
+        format: String,
+    },
+    /// Start a background agent that holds session credentials for all terminals
+    Agent {
+        #[command(subcommand)]
+        action: Option<AgentCommand>,
+
+        /// Run in the foreground instead of detaching
+        #[arg(long)]
+        foreground: bool,
+
+        /// Shut down after this many minutes without requests (0 = never)
+        #[arg(long, value_name = "MINUTES", default_value_t = agent::DEFAULT_IDLE_TIMEOUT_MINUTES)]
+        idle_timeout: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum AgentCommand {
+    /// Show whether the agent is running and which sessions it holds
+    Status,
+    /// Forget all sessions held by the agent
+    Lock,
+    /// Stop the agent
+    Stop,
 }
 
-#[derive(Debug, Deserialize)]
-struct AwsConfig {
-    #[serde(flatten)]
-    sections: HashMap<String, HashMap<String, String>>,
+const DEFAULT_PROFILE_NAME_TEMPLATE: &str = "{account_name}-{role_name}";
+const DEFAULT_PROMPT_TEMPLATE: &str = "aws:{profile} {remaining}";
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct CredentialProcessOutput {
+    version: u8,
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default)]
@@ -37,7 +243,7 @@ struct OktaAwsCli {
     profiles: HashMap<String, OktaProfile>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct OktaProfile {
     #[serde(rename = "org-domain", skip_serializing_if = "Option::is_none")]
     org_domain: Option<String>,
@@ -54,35 +260,65 @@ struct OktaProfile {
     aws_iam_idp: Option<String>,
 }
 
-#[derive(Debug, Clone)]
-struct Profile {
-    name: String,
-    is_sso: bool,
-    is_okta: bool,
-    sso_start_url: Option<String>,
-    sso_region: Option<String>,
-    sso_account_id: Option<String>,
-    sso_role_name: Option<String>,
-    region: Option<String>,
-    // Okta-specific fields
-    okta_org_domain: Option<String>,
-    okta_oidc_client_id: Option<String>,
-    okta_aws_account_federation_app_id: Option<String>,
-    okta_aws_iam_role: Option<String>,
-    okta_aws_iam_idp: Option<String>,
+// Stored session credentials this close to expiry are renewed instead of reused
+const SESSION_REFRESH_MARGIN_MINUTES: i64 = 15;
+
+// Variables a session may export to the shell
+const SESSION_ENV_VARS: [&str; 9] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_CREDENTIAL_EXPIRATION",
+    "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+    "AWS_CONTAINER_AUTHORIZATION_TOKEN",
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "AWS_PROFILE",
+];
+
+// Variables aaa never sets that still change which identity SDKs and the CLI
+// resolve; a session shell must not inherit them
+const FOREIGN_CREDENTIAL_ENV_VARS: [&str; 7] = [
+    "AWS_SECURITY_TOKEN",
+    "AWS_DEFAULT_PROFILE",
+    "AWS_ROLE_ARN",
+    "AWS_ROLE_SESSION_NAME",
+    "AWS_WEB_IDENTITY_TOKEN_FILE",
+    "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI",
+    "AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE",
+];
+
+const CREDENTIAL_SOURCES: [&str; 3] = ["Environment", "Ec2InstanceMetadata", "EcsContainer"];
+
+// Where the AWS CLI and SDKs look for the config and credentials files
+const CONFIG_FILE_ENV: &str = "AWS_CONFIG_FILE";
+const CREDENTIALS_FILE_ENV: &str = "AWS_SHARED_CREDENTIALS_FILE";
+// Where aaa keeps the okta.yaml of its Okta profiles
+const OKTA_CONFIG_ENV: &str = "AAA_OKTA_CONFIG";
+
+// The file named by the environment variable, with a leading ~ expanded like
+// the AWS CLI does, or the default location in the home directory
+fn file_path_from_env(env_var: &str, default: &[&str]) -> Result<PathBuf> {
+    let home = || dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"));
+
+    match env::var(env_var) {
+        Ok(path) if !path.is_empty() => match path.strip_prefix("~/") {
+            Some(relative) => Ok(home()?.join(relative)),
+            None => Ok(PathBuf::from(path)),
+        },
+        _ => Ok(default.iter().fold(home()?, |path, part| path.join(part))),
+    }
 }
 
 fn get_aws_config_path() -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    Ok(home.join(".aws").join("config"))
+    file_path_from_env(CONFIG_FILE_ENV, &[".aws", "config"])
 }
 
 fn get_aws_credentials_path() -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    Ok(home.join(".aws").join("credentials"))
+    file_path_from_env(CREDENTIALS_FILE_ENV, &[".aws", "credentials"])
 }
 
-fn parse_aws_config() -> Result<Vec<Profile>> {
+fn load_aws_config() -> Result<ini::Document> {
     let config_path = get_aws_config_path()?;
 
     if !config_path.exists() {
@@ -91,20 +327,62 @@ fn parse_aws_config() -> Result<Vec<Profile>> {
             fs::create_dir_all(parent).context("Failed to create .aws directory")?;
         }
         fs::write(&config_path, "").context("Failed to create config file")?;
-        return Ok(Vec::new());
     }
 
-    let content = fs::read_to_string(&config_path).context("Failed to read AWS config file")?;
+    ini::Document::load(&config_path)
+}
+
+fn load_aws_credentials() -> Result<ini::Document> {
+    ini::Document::load(&get_aws_credentials_path()?)
+}
+
+// Config file section of a profile: [default] or [profile <name>]
+fn profile_section_name(profile_name: &str) -> String {
+    if profile_name == "default" {
+        "default".to_string()
+    } else {
+        format!("profile {}", profile_name)
+    }
+}
 
-    if content.trim().is_empty() {
-        return Ok(Vec::new());
+fn collect_sso_sessions(config: &ini::Document) -> HashMap<String, SsoSession> {
+    let mut sessions = HashMap::new();
+
+    for section_name in config.section_names() {
+        if let Some(name) = section_name.strip_prefix("sso-session ") {
+            let section_data = config.section(&section_name).unwrap_or_default();
+            let session = SsoSession {
+                name: name.to_string(),
+                sso_start_url: section_data.get("sso_start_url").cloned(),
+                sso_region: section_data.get("sso_region").cloned(),
+                sso_registration_scopes: section_data.get("sso_registration_scopes").cloned(),
+            };
+            sessions.insert(session.name.clone(), session);
+        }
     }
 
+    sessions
+}
+
+fn parse_sso_sessions() -> Result<Vec<SsoSession>> {
+    let config = load_aws_config()?;
+
+    let mut sessions: Vec<SsoSession> = collect_sso_sessions(&config).into_values().collect();
+    sessions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(sessions)
+}
+
+// Profiles of the config file in file order, followed by those that only
+// exist in the credentials file
+fn parse_aws_config() -> Result<Vec<Profile>> {
+    let config = load_aws_config()?;
+    let credentials = load_aws_credentials()?;
+    let sso_sessions = collect_sso_sessions(&config);
+
     let mut profiles = Vec::new();
-    let config: AwsConfig =
-        serde_ini::from_str(&content).context("Failed to parse AWS config file")?;
 
-    for (section_name, section_data) in config.sections {
+    for section_name in config.section_names() {
         let profile_name = if section_name == "default" {
             "default".to_string()
         } else if let Some(name) = section_name.strip_prefix("profile ") {
@@ -113,32 +391,43 @@ fn parse_aws_config() -> Result<Vec<Profile>> {
             continue;
         };
 
-        // Determine profile type: Okta, SSO, or Standard
-        // Okta takes precedence if both are present (though this shouldn't happen)
-        let is_okta = section_data.contains_key("okta_org_domain");
-        let is_sso = !is_okta && section_data.contains_key("sso_start_url");
-
-        let profile = Profile {
-            name: profile_name,
-            is_sso,
-            is_okta,
-            sso_start_url: section_data.get("sso_start_url").cloned(),
-            sso_region: section_data.get("sso_region").cloned(),
-            sso_account_id: section_data.get("sso_account_id").cloned(),
-            sso_role_name: section_data.get("sso_role_name").cloned(),
-            region: section_data.get("region").cloned(),
-            okta_org_domain: section_data.get("okta_org_domain").cloned(),
-            okta_oidc_client_id: section_data.get("okta_oidc_client_id").cloned(),
-            okta_aws_account_federation_app_id: section_data
-                .get("okta_aws_account_federation_app_id")
-                .cloned(),
-            okta_aws_iam_role: section_data.get("okta_aws_iam_role").cloned(),
-            okta_aws_iam_idp: section_data.get("okta_aws_iam_idp").cloned(),
+        let source = if credentials.has_section(&profile_name) {
+            ProfileSource::Both
+        } else {
+            ProfileSource::Config
         };
+        let profile = Profile::from_section(
+            profile_name,
+            config.entries(&section_name),
+            &sso_sessions,
+            source,
+        );
 
         profiles.push(profile);
     }
 
+    // The AWS CLI also reads settings such as region from the credentials
+    // file; the keys themselves are not profile settings
+    for profile_name in credentials.section_names() {
+        if profiles.iter().any(|p| p.name == profile_name) {
+            continue;
+        }
+
+        let entries = credentials
+            .entries(&profile_name)
+            .into_iter()
+            .filter(|(key, _)| {
+                !CREDENTIALS_FILE_KEYS.contains(&key.as_str()) && key != "x_security_token_expires"
+            })
+            .collect();
+        profiles.push(Profile::from_section(
+            profile_name,
+            entries,
+            &sso_sessions,
+            ProfileSource::Credentials,
+        ));
+    }
+
     Ok(profiles)
 }
 
@@ -164,16 +453,30 @@ fn create_new_sso_profile() -> Result<Profile> {
         return Err(anyhow!("Profile '{}' already exists", profile_name));
     }
 
-    let sso_start_url = Text::new("SSO start URL:")
-        .with_help_message("The AWS SSO portal URL (e.g., https://my-sso-portal.awsapps.com/start)")
-        .prompt()
-        .context("Failed to get SSO start URL")?;
-
-    let sso_region = Text::new("SSO region:")
-        .with_default("us-east-1")
-        .with_help_message("The AWS region where your SSO directory is hosted")
-        .prompt()
-        .context("Failed to get SSO region")?;
+    let session = select_sso_session()?;
+
+    let (sso_start_url, sso_region) = match &session {
+        Some(session) => (
+            session.sso_start_url.clone().unwrap_or_default(),
+            session.sso_region.clone().unwrap_or_default(),
+        ),
+        None => {
+            let sso_start_url = Text::new("SSO start URL:")
+                .with_help_message(
+                    "The AWS SSO portal URL (e.g., https://my-sso-portal.awsapps.com/start)",
+                )
+                .prompt()
+                .context("Failed to get SSO start URL")?;
+
+            let sso_region = Text::new("SSO region:")
+                .with_default("us-east-1")
+                .with_help_message("The AWS region where your SSO directory is hosted")
+                .prompt()
+                .context("Failed to get SSO region")?;
+
+            (sso_start_url, sso_region)
+        }
+    };
 
     let sso_account_id = Text::new("AWS account ID:")
         .with_help_message("The 12-digit AWS account ID")
@@ -193,18 +496,19 @@ fn create_new_sso_profile() -> Result<Profile> {
 
     let profile = Profile {
         name: profile_name.clone(),
-        is_sso: true,
-        is_okta: false,
-        sso_start_url: Some(sso_start_url.clone()),
-        sso_region: Some(sso_region.clone()),
-        sso_account_id: Some(sso_account_id.clone()),
-        sso_role_name: Some(sso_role_name.clone()),
+        kind: ProfileKind::Sso(SsoSettings {
+            session: session.as_ref().map(|s| s.name.clone()),
+            start_url: Some(sso_start_url.clone()),
+            region: Some(sso_region.clone()),
+            registration_scopes: session
+                .as_ref()
+                .and_then(|s| s.sso_registration_scopes.clone()),
+            account_id: Some(sso_account_id.clone()),
+            role_name: Some(sso_role_name.clone()),
+        }),
         region: Some(region.clone()),
-        okta_org_domain: None,
-        okta_oidc_client_id: None,
-        okta_aws_account_federation_app_id: None,
-        okta_aws_iam_role: None,
-        okta_aws_iam_idp: None,
+        extra: Vec::new(),
+        source: ProfileSource::Config,
     };
 
     // Write profile to config file
@@ -217,6 +521,76 @@ fn create_new_sso_profile() -> Result<Profile> {
     Ok(profile)
 }
 
+fn select_sso_session() -> Result<Option<SsoSession>> {
+    let sessions = parse_sso_sessions()?;
+
+    let mut options: Vec<String> = Vec::new();
+    options.push("➕ Create a new SSO session".to_string());
+    for session in &sessions {
+        options.push(format!(
+            "   {} ({})",
+            session.name,
+            session.sso_start_url.as_deref().unwrap_or("no start URL")
+        ));
+    }
+    options.push("   No session (legacy configuration)".to_string());
+
+    let selection = Select::new("SSO session:", options)
+        .with_help_message("SSO sessions share the start URL and token between profiles")
+        .raw_prompt()
+        .context("Failed to select SSO session")?;
+
+    if selection.index == 0 {
+        create_new_sso_session().map(Some)
+    } else if selection.index <= sessions.len() {
+        Ok(Some(sessions[selection.index - 1].clone()))
+    } else {
+        Ok(None)
+    }
+}
+
+fn create_new_sso_session() -> Result<SsoSession> {
+    let session_name = Text::new("SSO session name:")
+        .with_help_message("A unique name for this SSO session (e.g., my-org)")
+        .prompt()
+        .context("Failed to get SSO session name")?
+        .trim()
+        .to_string();
+
+    if session_name.is_empty() {
+        return Err(anyhow!("SSO session name cannot be empty"));
+    }
+
+    // Check if session already exists
+    let existing_sessions = parse_sso_sessions()?;
+    if existing_sessions.iter().any(|s| s.name == session_name) {
+        return Err(anyhow!("SSO session '{}' already exists", session_name));
+    }
+
+    let sso_start_url = Text::new("SSO start URL:")
+        .with_help_message("The AWS SSO portal URL (e.g., https://my-sso-portal.awsapps.com/start)")
+        .prompt()
+        .context("Failed to get SSO start URL")?;
+
+    let sso_region = Text::new("SSO region:")
+        .with_default("us-east-1")
+        .with_help_message("The AWS region where your SSO directory is hosted")
+        .prompt()
+        .context("Failed to get SSO region")?;
+
+    let session = SsoSession {
+        name: session_name,
+        sso_start_url: Some(sso_start_url),
+        sso_region: Some(sso_region),
+        sso_registration_scopes: Some("sso:account:access".to_string()),
+    };
+
+    // Write session to config file
+    save_sso_session_to_config(&session)?;
+
+    Ok(session)
+}
+
 fn create_new_okta_profile() -> Result<Profile> {
     println!();
     println!("{}", "Create New Okta AWS Profile".bold().green());
@@ -274,39 +648,39 @@ fn create_new_okta_profile() -> Result<Profile> {
         .prompt()
         .context("Failed to get region")?;
 
-    let profile = Profile {
-        name: profile_name.clone(),
-        is_sso: false,
-        is_okta: true,
-        sso_start_url: None,
-        sso_region: None,
-        sso_account_id: None,
-        sso_role_name: None,
-        region: Some(region.clone()),
-        okta_org_domain: Some(okta_org_domain.clone()),
-        okta_oidc_client_id: Some(okta_oidc_client_id.clone()),
-        okta_aws_account_federation_app_id: if okta_aws_account_federation_app_id.is_empty() {
+    let okta = OktaSettings {
+        org_domain: okta_org_domain.clone(),
+        oidc_client_id: Some(okta_oidc_client_id.clone()),
+        federation_app_id: if okta_aws_account_federation_app_id.is_empty() {
             None
         } else {
             Some(okta_aws_account_federation_app_id.clone())
         },
-        okta_aws_iam_role: if okta_aws_iam_role.is_empty() {
+        iam_role: if okta_aws_iam_role.is_empty() {
             None
         } else {
             Some(okta_aws_iam_role.clone())
         },
-        okta_aws_iam_idp: if okta_aws_iam_idp.is_empty() {
+        iam_idp: if okta_aws_iam_idp.is_empty() {
             None
         } else {
             Some(okta_aws_iam_idp.clone())
         },
     };
 
+    let profile = Profile {
+        name: profile_name.clone(),
+        kind: ProfileKind::Okta(okta.clone()),
+        region: Some(region.clone()),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
+    };
+
     // Write profile to config file
     save_profile_to_config(&profile)?;
 
     // Create okta.yaml configuration
-    create_okta_yaml(&profile)?;
+    create_okta_yaml(&profile_name, &okta)?;
 
     println!();
     println!("{}", "✓ Profile created successfully!".green().bold());
@@ -351,21 +725,11 @@ fn create_new_credentials_profile() -> Result<Profile> {
     }
 
     // Check if profile already exists in credentials file
-    let creds_path = get_aws_credentials_path()?;
-    if creds_path.exists() {
-        let existing_creds =
-            fs::read_to_string(&creds_path).context("Failed to read existing credentials file")?;
-
-        // Use a more precise check: look for profile name as a complete section header
-        let profile_section = format!("[{}]", profile_name);
-        for line in existing_creds.lines() {
-            if line.trim() == profile_section {
-                return Err(anyhow!(
-                    "Profile '{}' already exists in credentials file",
-                    profile_name
-                ));
-            }
-        }
+    if load_aws_credentials()?.has_section(&profile_name) {
+        return Err(anyhow!(
+            "Profile '{}' already exists in credentials file",
+            profile_name
+        ));
     }
 
     let access_key_id = Text::new("AWS Access Key ID:")
@@ -419,20 +783,31 @@ fn create_new_credentials_profile() -> Result<Profile> {
         ));
     }
 
+    let mfa_serial = Text::new("MFA device ARN (optional):")
+        .with_help_message(
+            "ARN of the MFA device (e.g., arn:aws:iam::123456789012:mfa/my-user), leave empty if not required",
+        )
+        .prompt()
+        .context("Failed to prompt for MFA device ARN")?
+        .trim()
+        .to_string();
+
+    if !mfa_serial.is_empty() && !mfa_serial.starts_with("arn:") {
+        return Err(anyhow!("MFA device ARN must start with 'arn:'"));
+    }
+
     let profile = Profile {
         name: profile_name.clone(),
-        is_sso: false,
-        is_okta: false,
-        sso_start_url: None,
-        sso_region: None,
-        sso_account_id: None,
-        sso_role_name: None,
+        kind: ProfileKind::Static {
+            mfa_serial: if mfa_serial.is_empty() {
+                None
+            } else {
+                Some(mfa_serial.clone())
+            },
+        },
         region: Some(region.clone()),
-        okta_org_domain: None,
-        okta_oidc_client_id: None,
-        okta_aws_account_federation_app_id: None,
-        okta_aws_iam_role: None,
-        okta_aws_iam_idp: None,
+        extra: Vec::new(),
+        source: ProfileSource::Both,
     };
 
     // Write profile to config file
@@ -448,212 +823,1270 @@ fn create_new_credentials_profile() -> Result<Profile> {
     Ok(profile)
 }
 
-fn save_credentials_to_file(
-    profile_name: &str,
-    access_key_id: &str,
-    secret_access_key: &str,
-) -> Result<()> {
-    let creds_path = get_aws_credentials_path()?;
-
-    // Ensure the directory exists
-    if let Some(parent) = creds_path.parent() {
-        fs::create_dir_all(parent).context("Failed to create .aws directory")?;
-    }
-
-    // Read existing content or create empty
-    let existing_content = if creds_path.exists() {
-        fs::read_to_string(&creds_path).context("Failed to read existing credentials file")?
-    } else {
-        String::new()
-    };
+fn create_new_assume_role_profile() -> Result<Profile> {
+    println!();
+    println!("{}", "Create New AWS Assume Role Profile".bold().green());
+    println!();
 
-    // Append new credentials
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&creds_path)
-        .context("Failed to open credentials file")?;
+    let profile_name = Text::new("Profile name:")
+        .with_help_message("A unique name for this profile (e.g., my-org-prod)")
+        .prompt()
+        .context("Failed to get profile name")?
+        .trim()
+        .to_string();
 
-    // Add newline if file is not empty
-    if !existing_content.is_empty() && !existing_content.ends_with('\n') {
-        writeln!(file)?;
+    if profile_name.is_empty() {
+        return Err(anyhow!("Profile name cannot be empty"));
     }
 
-    // Write credentials section
-    writeln!(file, "[{}]", profile_name)?;
-    writeln!(file, "aws_access_key_id = {}", access_key_id)?;
-    writeln!(file, "aws_secret_access_key = {}", secret_access_key)?;
-
-    Ok(())
-}
+    // Check if profile already exists
+    let existing_profiles = parse_aws_config()?;
+    if existing_profiles.iter().any(|p| p.name == profile_name) {
+        return Err(anyhow!("Profile '{}' already exists", profile_name));
+    }
 
-fn get_okta_config_path() -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
-    Ok(home.join(".okta").join("okta.yaml"))
-}
+    let role_arn = Text::new("Role ARN:")
+        .with_help_message("The IAM role to assume (e.g., arn:aws:iam::123456789012:role/MyRole)")
+        .prompt()
+        .context("Failed to get role ARN")?
+        .trim()
+        .to_string();
 
-fn create_okta_yaml(profile: &Profile) -> Result<()> {
-    let okta_config_path = get_okta_config_path()?;
+    if !role_arn.starts_with("arn:") {
+        return Err(anyhow!("Role ARN must start with 'arn:'"));
+    }
 
-    // Ensure the directory exists
-    if let Some(parent) = okta_config_path.parent() {
-        fs::create_dir_all(parent).context("Failed to create .okta directory")?;
+    // Source credentials: an existing profile or one of the credential sources
+    let mut source_options: Vec<String> = existing_profiles
+        .iter()
+        .map(|p| format!("Profile: {}", p.name))
+        .collect();
+    for credential_source in CREDENTIAL_SOURCES {
+        source_options.push(format!("Credential source: {}", credential_source));
     }
 
-    // Read existing configuration or create a new one
-    let mut config: OktaYamlConfig = if okta_config_path.exists() {
-        let content = fs::read_to_string(&okta_config_path)
-            .context("Failed to read existing okta.yaml file")?;
-        serde_yaml::from_str(&content).unwrap_or_default()
-    } else {
-        OktaYamlConfig::default()
-    };
+    let source = Select::new("Source credentials:", source_options)
+        .with_help_message("Where the credentials used to assume the role come from")
+        .with_page_size(10)
+        .raw_prompt()
+        .context("Failed to select source credentials")?;
 
-    // Create or update the profile
-    let okta_profile = OktaProfile {
-        org_domain: profile.okta_org_domain.clone(),
-        oidc_client_id: profile.okta_oidc_client_id.clone(),
-        aws_acct_fed_app_id: profile.okta_aws_account_federation_app_id.clone(),
-        aws_iam_role: profile.okta_aws_iam_role.clone(),
-        aws_iam_idp: profile.okta_aws_iam_idp.clone(),
+    let (source_profile, credential_source) = if source.index < existing_profiles.len() {
+        (Some(existing_profiles[source.index].name.clone()), None)
+    } else {
+        let credential_source = CREDENTIAL_SOURCES[source.index - existing_profiles.len()];
+        (None, Some(credential_source.to_string()))
     };
 
-    // Insert or replace the profile
-    config
-        .awscli
-        .profiles
-        .insert(profile.name.clone(), okta_profile);
-
-    // Write the updated configuration
-    let yaml_content =
-        serde_yaml::to_string(&config).context("Failed to serialize okta.yaml config")?;
-    fs::write(&okta_config_path, yaml_content).context("Failed to write okta.yaml file")?;
+    let external_id = Text::new("External ID (optional):")
+        .with_help_message("External ID required by the role's trust policy, if any")
+        .prompt()
+        .context("Failed to prompt for external ID")?;
 
-    println!();
-    println!(
-        "{}",
-        format!(
-            "✓ Created/updated ~/.okta/okta.yaml with profile '{}'",
-            profile.name
+    let role_session_name = Text::new("Role session name (optional):")
+        .with_help_message(
+            "Name recorded in CloudTrail for this session (default: aaa-<timestamp>)",
         )
-        .green()
-    );
-
-    Ok(())
-}
+        .prompt()
+        .context("Failed to prompt for role session name")?;
 
-fn save_profile_to_config(profile: &Profile) -> Result<()> {
-    let config_path = get_aws_config_path()?;
+    let duration_seconds = Text::new("Session duration in seconds (optional):")
+        .with_help_message("Between 900 and the role's maximum session duration (default: 3600)")
+        .prompt()
+        .context("Failed to prompt for session duration")?;
 
-    // Ensure the directory exists
-    if let Some(parent) = config_path.parent() {
-        fs::create_dir_all(parent).context("Failed to create .aws directory")?;
+    if !duration_seconds.is_empty() && duration_seconds.parse::<i32>().is_err() {
+        return Err(anyhow!("Session duration must be a number of seconds"));
     }
 
-    // Read existing content or create empty
-    let existing_content = if config_path.exists() {
-        fs::read_to_string(&config_path).context("Failed to read existing config file")?
-    } else {
-        String::new()
+    let mfa_serial = Text::new("MFA device ARN (optional):")
+        .with_help_message("ARN of the MFA device required by the role's trust policy, if any")
+        .prompt()
+        .context("Failed to prompt for MFA device ARN")?
+        .trim()
+        .to_string();
+
+    let region = Text::new("Default region:")
+        .with_default("us-east-1")
+        .with_help_message("Default AWS region for this profile")
+        .prompt()
+        .context("Failed to get region")?;
+
+    let profile = Profile {
+        name: profile_name.clone(),
+        kind: ProfileKind::AssumeRole(AssumeRoleSettings {
+            role_arn: role_arn.clone(),
+            source_profile,
+            credential_source,
+            external_id: if external_id.is_empty() {
+                None
+            } else {
+                Some(external_id.clone())
+            },
+            role_session_name: if role_session_name.is_empty() {
+                None
+            } else {
+                Some(role_session_name.clone())
+            },
+            duration_seconds: if duration_seconds.is_empty() {
+                None
+            } else {
+                Some(duration_seconds.clone())
+            },
+            mfa_serial: if mfa_serial.is_empty() {
+                None
+            } else {
+                Some(mfa_serial.clone())
+            },
+        }),
+        region: Some(region.clone()),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
     };
 
-    // Append new profile
-    let mut file = fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&config_path)
-        .context("Failed to open config file")?;
+    // Write profile to config file
+    save_profile_to_config(&profile)?;
 
-    // Add newline if file is not empty
-    if !existing_content.is_empty() && !existing_content.ends_with('\n') {
-        writeln!(file)?;
-    }
+    println!();
+    println!("{}", "✓ Profile created successfully!".green().bold());
+    println!();
 
-    // Write profile section
-    let section_name = if profile.name == "default" {
-        "[default]".to_string()
-    } else {
-        format!("[profile {}]", profile.name)
-    };
+    Ok(profile)
+}
 
-    writeln!(file, "{}", section_name)?;
+fn create_new_saml_profile() -> Result<Profile> {
+    println!();
+    println!("{}", "Create New AWS SAML Profile".bold().green());
+    println!();
 
-    // Write SSO fields if present
-    if let Some(sso_start_url) = &profile.sso_start_url {
-        writeln!(file, "sso_start_url = {}", sso_start_url)?;
-    }
-    if let Some(sso_region) = &profile.sso_region {
-        writeln!(file, "sso_region = {}", sso_region)?;
-    }
-    if let Some(sso_account_id) = &profile.sso_account_id {
-        writeln!(file, "sso_account_id = {}", sso_account_id)?;
-    }
-    if let Some(sso_role_name) = &profile.sso_role_name {
-        writeln!(file, "sso_role_name = {}", sso_role_name)?;
-    }
+    let profile_name = Text::new("Profile name:")
+        .with_help_message("A unique name for this profile (e.g., my-org-adfs)")
+        .prompt()
+        .context("Failed to get profile name")?
+        .trim()
+        .to_string();
 
-    // Write Okta fields if present
-    if let Some(okta_org_domain) = &profile.okta_org_domain {
-        writeln!(file, "okta_org_domain = {}", okta_org_domain)?;
-    }
-    if let Some(okta_oidc_client_id) = &profile.okta_oidc_client_id {
-        writeln!(file, "okta_oidc_client_id = {}", okta_oidc_client_id)?;
-    }
-    if let Some(okta_aws_account_federation_app_id) = &profile.okta_aws_account_federation_app_id {
-        writeln!(
-            file,
-            "okta_aws_account_federation_app_id = {}",
-            okta_aws_account_federation_app_id
-        )?;
-    }
-    if let Some(okta_aws_iam_role) = &profile.okta_aws_iam_role {
-        writeln!(file, "okta_aws_iam_role = {}", okta_aws_iam_role)?;
-    }
-    if let Some(okta_aws_iam_idp) = &profile.okta_aws_iam_idp {
-        writeln!(file, "okta_aws_iam_idp = {}", okta_aws_iam_idp)?;
+    if profile_name.is_empty() {
+        return Err(anyhow!("Profile name cannot be empty"));
     }
 
-    // Write common region field
-    if let Some(region) = &profile.region {
-        writeln!(file, "region = {}", region)?;
+    // Check if profile already exists
+    let existing_profiles = parse_aws_config()?;
+    if existing_profiles.iter().any(|p| p.name == profile_name) {
+        return Err(anyhow!("Profile '{}' already exists", profile_name));
     }
 
-    Ok(())
-}
+    let saml_idp_url = Text::new("IdP sign-in URL (optional):")
+        .with_help_message(
+            "IdP-initiated sign-in URL for AWS, leave empty to paste the SAMLResponse instead",
+        )
+        .prompt()
+        .context("Failed to prompt for IdP sign-in URL")?
+        .trim()
+        .to_string();
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+    if !saml_idp_url.is_empty()
+        && !saml_idp_url.starts_with("https://")
+        && !saml_idp_url.starts_with("http://")
+    {
+        return Err(anyhow!("IdP sign-in URL must start with 'https://'"));
+    }
 
-    let mut profiles = parse_aws_config().context("Failed to parse AWS config")?;
+    let saml_provider_arn = Text::new("AWS IAM Identity Provider ARN (optional):")
+        .with_help_message("AWS IAM IdP ARN (e.g., arn:aws:iam::123456789012:saml-provider/my-idp)")
+        .prompt()
+        .context("Failed to prompt for AWS IAM IdP")?
+        .trim()
+        .to_string();
 
-    // If profile specified via command line, use it directly
-    if let Some(profile_name) = cli.profile {
+    // Without either of them the profile could not be told apart from a standard one
+    if saml_idp_url.is_empty() && saml_provider_arn.is_empty() {
+        return Err(anyhow!(
+            "Either an IdP sign-in URL or an Identity Provider ARN is required"
+        ));
+    }
+
+    let saml_role_arn = Text::new("AWS IAM Role ARN (optional):")
+        .with_help_message(
+            "AWS IAM Role ARN to assume (e.g., arn:aws:iam::123456789012:role/MyRole)",
+        )
+        .prompt()
+        .context("Failed to prompt for AWS IAM role")?
+        .trim()
+        .to_string();
+
+    let region = Text::new("Default region:")
+        .with_default("us-east-1")
+        .with_help_message("Default AWS region for this profile")
+        .prompt()
+        .context("Failed to get region")?;
+
+    let profile = Profile {
+        name: profile_name.clone(),
+        kind: ProfileKind::Saml(SamlSettings {
+            idp_url: if saml_idp_url.is_empty() {
+                None
+            } else {
+                Some(saml_idp_url.clone())
+            },
+            provider_arn: if saml_provider_arn.is_empty() {
+                None
+            } else {
+                Some(saml_provider_arn.clone())
+            },
+            role_arn: if saml_role_arn.is_empty() {
+                None
+            } else {
+                Some(saml_role_arn.clone())
+            },
+        }),
+        region: Some(region.clone()),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
+    };
+
+    // Write profile to config file
+    save_profile_to_config(&profile)?;
+
+    println!();
+    println!("{}", "✓ Profile created successfully!".green().bold());
+    println!();
+
+    Ok(profile)
+}
+
+fn create_new_web_identity_profile() -> Result<Profile> {
+    println!();
+    println!("{}", "Create New AWS Web Identity Profile".bold().green());
+    println!();
+
+    let profile_name = Text::new("Profile name:")
+        .with_help_message("A unique name for this profile (e.g., ci-deploy)")
+        .prompt()
+        .context("Failed to get profile name")?
+        .trim()
+        .to_string();
+
+    if profile_name.is_empty() {
+        return Err(anyhow!("Profile name cannot be empty"));
+    }
+
+    // Check if profile already exists
+    let existing_profiles = parse_aws_config()?;
+    if existing_profiles.iter().any(|p| p.name == profile_name) {
+        return Err(anyhow!("Profile '{}' already exists", profile_name));
+    }
+
+    let role_arn = Text::new("Role ARN:")
+        .with_help_message("The IAM role to assume (e.g., arn:aws:iam::123456789012:role/MyRole)")
+        .prompt()
+        .context("Failed to get role ARN")?
+        .trim()
+        .to_string();
+
+    if !role_arn.starts_with("arn:") {
+        return Err(anyhow!("Role ARN must start with 'arn:'"));
+    }
+
+    let token_source = Select::new(
+        "Token source:",
+        vec![
+            "Token file",
+            "GitHub Actions",
+            "GitLab CI",
+            "Auto-detect CI provider",
+        ],
+    )
+    .with_help_message("Where the OIDC token comes from")
+    .raw_prompt()
+    .context("Failed to select token source")?;
+
+    let mut web_identity_token_file = None;
+    let mut web_identity_provider = None;
+    let mut web_identity_audience = None;
+    let mut web_identity_token_env = None;
+
+    if token_source.index == 0 {
+        let token_file = Text::new("Web identity token file:")
+            .with_help_message(
+                "Path to the OIDC token (e.g., /var/run/secrets/eks.amazonaws.com/serviceaccount/token)",
+            )
+            .prompt()
+            .context("Failed to get web identity token file")?
+            .trim()
+            .to_string();
+
+        if token_file.is_empty() {
+            return Err(anyhow!("Web identity token file cannot be empty"));
+        }
+
+        if !PathBuf::from(&token_file).exists() {
+            println!(
+                "{}",
+                format!(
+                    "Warning: '{}' does not exist yet, it must be present when the profile is used",
+                    token_file
+                )
+                .yellow()
+            );
+        }
+
+        web_identity_token_file = Some(token_file);
+    } else {
+        let provider = ["github", "gitlab", "auto"][token_source.index - 1];
+
+        let audience = Text::new("Audience:")
+            .with_default(web_identity::DEFAULT_AUDIENCE)
+            .with_help_message(
+                "Audience of the OIDC token, as configured on the IAM identity provider",
+            )
+            .prompt()
+            .context("Failed to get audience")?
+            .trim()
+            .to_string();
+
+        // GitHub tokens are requested with the default audience anyway
+        if !audience.is_empty()
+            && (provider == "gitlab" || audience != web_identity::DEFAULT_AUDIENCE)
+        {
+            web_identity_audience = Some(audience);
+        }
+
+        if provider != "github" {
+            let token_env = Text::new("GitLab ID token variable:")
+                .with_default(web_identity::DEFAULT_GITLAB_TOKEN_ENV)
+                .with_help_message("Variable declared under 'id_tokens:' in .gitlab-ci.yml")
+                .prompt()
+                .context("Failed to get GitLab ID token variable")?
+                .trim()
+                .to_string();
+
+            if !token_env.is_empty() && token_env != web_identity::DEFAULT_GITLAB_TOKEN_ENV {
+                web_identity_token_env = Some(token_env);
+            }
+        }
+
+        web_identity_provider = Some(provider.to_string());
+    }
+
+    let role_session_name = Text::new("Role session name (optional):")
+        .with_help_message(
+            "Name recorded in CloudTrail for this session (default: aaa-<timestamp>)",
+        )
+        .prompt()
+        .context("Failed to prompt for role session name")?;
+
+    let duration_seconds = Text::new("Session duration in seconds (optional):")
+        .with_help_message("Between 900 and the role's maximum session duration (default: 3600)")
+        .prompt()
+        .context("Failed to prompt for session duration")?;
+
+    if !duration_seconds.is_empty() && duration_seconds.parse::<i32>().is_err() {
+        return Err(anyhow!("Session duration must be a number of seconds"));
+    }
+
+    let region = Text::new("Default region:")
+        .with_default("us-east-1")
+        .with_help_message("Default AWS region for this profile")
+        .prompt()
+        .context("Failed to get region")?;
+
+    let profile = Profile {
+        name: profile_name.clone(),
+        kind: ProfileKind::WebIdentity(WebIdentitySettings {
+            role_arn: role_arn.clone(),
+            token_file: web_identity_token_file,
+            provider: web_identity_provider,
+            audience: web_identity_audience,
+            token_env: web_identity_token_env,
+            role_session_name: if role_session_name.is_empty() {
+                None
+            } else {
+                Some(role_session_name.clone())
+            },
+            duration_seconds: if duration_seconds.is_empty() {
+                None
+            } else {
+                Some(duration_seconds.clone())
+            },
+        }),
+        region: Some(region.clone()),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
+    };
+
+    // Write profile to config file
+    save_profile_to_config(&profile)?;
+
+    println!();
+    println!("{}", "✓ Profile created successfully!".green().bold());
+    println!();
+
+    Ok(profile)
+}
+
+fn create_new_roles_anywhere_profile() -> Result<Profile> {
+    println!();
+    println!(
+        "{}",
+        "Create New AWS IAM Roles Anywhere Profile".bold().green()
+    );
+    println!();
+
+    let profile_name = Text::new("Profile name:")
+        .with_help_message("A unique name for this profile (e.g., build-host)")
+        .prompt()
+        .context("Failed to get profile name")?
+        .trim()
+        .to_string();
+
+    if profile_name.is_empty() {
+        return Err(anyhow!("Profile name cannot be empty"));
+    }
+
+    // Check if profile already exists
+    let existing_profiles = parse_aws_config()?;
+    if existing_profiles.iter().any(|p| p.name == profile_name) {
+        return Err(anyhow!("Profile '{}' already exists", profile_name));
+    }
+
+    let certificate = Text::new("Certificate file:")
+        .with_help_message("PEM file with the X.509 certificate issued by the trust anchor")
+        .prompt()
+        .context("Failed to get certificate file")?
+        .trim()
+        .to_string();
+
+    // Parse it right away so a wrong path or format is caught before saving
+    roles_anywhere::load_certificate(&certificate)?;
+
+    let private_key = Text::new("Private key file:")
+        .with_help_message("PEM file with the unencrypted RSA or EC P-256 private key")
+        .prompt()
+        .context("Failed to get private key file")?
+        .trim()
+        .to_string();
+
+    if private_key.is_empty() {
+        return Err(anyhow!("Private key file cannot be empty"));
+    }
+
+    let certificate_chain = Text::new("Certificate chain file (optional):")
+        .with_help_message("PEM file with intermediate CA certificates, if any")
+        .prompt()
+        .context("Failed to prompt for certificate chain file")?
+        .trim()
+        .to_string();
+
+    let trust_anchor_arn = Text::new("Trust anchor ARN:")
+        .with_help_message(
+            "e.g., arn:aws:rolesanywhere:us-east-1:123456789012:trust-anchor/a1b2c3d4-...",
+        )
+        .prompt()
+        .context("Failed to get trust anchor ARN")?
+        .trim()
+        .to_string();
+
+    if !trust_anchor_arn.starts_with("arn:") {
+        return Err(anyhow!("Trust anchor ARN must start with 'arn:'"));
+    }
+
+    let profile_arn = Text::new("Roles Anywhere profile ARN:")
+        .with_help_message(
+            "e.g., arn:aws:rolesanywhere:us-east-1:123456789012:profile/a1b2c3d4-...",
+        )
+        .prompt()
+        .context("Failed to get Roles Anywhere profile ARN")?
+        .trim()
+        .to_string();
+
+    if !profile_arn.starts_with("arn:") {
+        return Err(anyhow!("Roles Anywhere profile ARN must start with 'arn:'"));
+    }
+
+    let role_arn = Text::new("Role ARN:")
+        .with_help_message("The IAM role to assume (e.g., arn:aws:iam::123456789012:role/MyRole)")
+        .prompt()
+        .context("Failed to get role ARN")?
+        .trim()
+        .to_string();
+
+    if !role_arn.starts_with("arn:") {
+        return Err(anyhow!("Role ARN must start with 'arn:'"));
+    }
+
+    let duration_seconds = Text::new("Session duration in seconds (optional):")
+        .with_help_message("Between 900 and 43200 (default: 3600)")
+        .prompt()
+        .context("Failed to prompt for session duration")?;
+
+    if !duration_seconds.is_empty() && duration_seconds.parse::<i32>().is_err() {
+        return Err(anyhow!("Session duration must be a number of seconds"));
+    }
+
+    let region = Text::new("Default region:")
+        .with_default("us-east-1")
+        .with_help_message("Default AWS region for this profile")
+        .prompt()
+        .context("Failed to get region")?;
+
+    let profile = Profile {
+        name: profile_name.clone(),
+        kind: ProfileKind::RolesAnywhere(RolesAnywhereSettings {
+            certificate: Some(certificate.clone()),
+            certificate_chain: if certificate_chain.is_empty() {
+                None
+            } else {
+                Some(certificate_chain.clone())
+            },
+            private_key: Some(private_key.clone()),
+            trust_anchor_arn: trust_anchor_arn.clone(),
+            profile_arn: Some(profile_arn.clone()),
+            role_arn: Some(role_arn.clone()),
+            role_session_name: None,
+            duration_seconds: if duration_seconds.is_empty() {
+                None
+            } else {
+                Some(duration_seconds.clone())
+            },
+        }),
+        region: Some(region.clone()),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
+    };
+
+    // Write profile to config file
+    save_profile_to_config(&profile)?;
+
+    println!();
+    println!("{}", "✓ Profile created successfully!".green().bold());
+    println!();
+
+    Ok(profile)
+}
+
+fn save_credentials_to_file(
+    profile_name: &str,
+    access_key_id: &str,
+    secret_access_key: &str,
+) -> Result<()> {
+    let creds_path = get_aws_credentials_path()?;
+    let mut credentials = ini::Document::load(&creds_path)?;
+
+    credentials.set(profile_name, "aws_access_key_id", access_key_id);
+    credentials.set(profile_name, "aws_secret_access_key", secret_access_key);
+
+    credentials.save(&creds_path)
+}
+
+// Updates the profile's section in the credentials file with temporary credentials,
+// the same way okta-aws-cli does with --write-aws-credentials
+fn save_session_credentials_to_file(profile_name: &str, credentials: &Credentials) -> Result<()> {
+    let creds_path = get_aws_credentials_path()?;
+    let mut file = ini::Document::load(&creds_path)?;
+
+    file.set(
+        profile_name,
+        "aws_access_key_id",
+        credentials.access_key_id(),
+    );
+    file.set(
+        profile_name,
+        "aws_secret_access_key",
+        credentials.secret_access_key(),
+    );
+    match credentials.session_token() {
+        Some(session_token) => file.set(profile_name, "aws_session_token", session_token),
+        None => {
+            file.remove(profile_name, "aws_session_token");
+        }
+    }
+    match credentials.expiry() {
+        Some(expiry) => file.set(
+            profile_name,
+            "x_security_token_expires",
+            &chrono::DateTime::<chrono::Utc>::from(expiry).to_rfc3339(),
+        ),
+        None => {
+            file.remove(profile_name, "x_security_token_expires");
+        }
+    }
+
+    file.save(&creds_path)
+}
+
+// A running aaa agent keeps a new federated session in memory only; otherwise
+// it goes to the credentials file for the AWS CLI, the SDKs and the next login
+async fn store_session_credentials(profile_name: &str, credentials: &Credentials) -> Result<()> {
+    if agent::store(profile_name, credentials).await {
+        return Ok(());
+    }
+    save_session_credentials_to_file(profile_name, credentials)
+}
+
+fn get_okta_config_path() -> Result<PathBuf> {
+    file_path_from_env(OKTA_CONFIG_ENV, &[".okta", "okta.yaml"])
+}
+
+fn load_okta_yaml() -> Result<OktaYamlConfig> {
+    let okta_config_path = get_okta_config_path()?;
+
+    // Read existing configuration or create a new one
+    if okta_config_path.exists() {
+        let content = fs::read_to_string(&okta_config_path)
+            .context("Failed to read existing okta.yaml file")?;
+        Ok(serde_yaml::from_str(&content).unwrap_or_default())
+    } else {
+        Ok(OktaYamlConfig::default())
+    }
+}
+
+fn save_okta_yaml(config: &OktaYamlConfig) -> Result<()> {
+    let okta_config_path = get_okta_config_path()?;
+
+    // Ensure the directory exists
+    if let Some(parent) = okta_config_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create .okta directory")?;
+    }
+
+    let yaml_content =
+        serde_yaml::to_string(config).context("Failed to serialize okta.yaml config")?;
+    fs::write(&okta_config_path, yaml_content).context("Failed to write okta.yaml file")
+}
+
+fn create_okta_yaml(profile_name: &str, okta: &OktaSettings) -> Result<()> {
+    let mut config = load_okta_yaml()?;
+
+    // Create or update the profile
+    let okta_profile = OktaProfile {
+        org_domain: Some(okta.org_domain.clone()),
+        oidc_client_id: okta.oidc_client_id.clone(),
+        aws_acct_fed_app_id: okta.federation_app_id.clone(),
+        aws_iam_role: okta.iam_role.clone(),
+        aws_iam_idp: okta.iam_idp.clone(),
+    };
+
+    // Insert or replace the profile
+    config
+        .awscli
+        .profiles
+        .insert(profile_name.to_string(), okta_profile);
+
+    // Write the updated configuration
+    save_okta_yaml(&config)?;
+
+    println!();
+    println!(
+        "{}",
+        format!(
+            "✓ Created/updated {} with profile '{}'",
+            get_okta_config_path()?.display(),
+            profile_name
+        )
+        .green()
+    );
+
+    Ok(())
+}
+
+fn save_sso_session_to_config(session: &SsoSession) -> Result<()> {
+    let config_path = get_aws_config_path()?;
+    let mut config = ini::Document::load(&config_path)?;
+    let section = format!("sso-session {}", session.name);
+
+    let entries = [
+        ("sso_start_url", &session.sso_start_url),
+        ("sso_region", &session.sso_region),
+        ("sso_registration_scopes", &session.sso_registration_scopes),
+    ];
+    for (key, value) in entries {
+        if let Some(value) = value {
+            config.set(&section, key, value);
+        }
+    }
+
+    config.save(&config_path)
+}
+
+fn save_profile_to_config(profile: &Profile) -> Result<()> {
+    let config_path = get_aws_config_path()?;
+    let mut config = ini::Document::load(&config_path)?;
+    let section = profile_section_name(&profile.name);
+
+    for (key, value) in profile.entries() {
+        config.set(&section, &key, &value);
+    }
+
+    config.save(&config_path)
+}
+
+// Keys that live in ~/.aws/credentials rather than ~/.aws/config
+const CREDENTIALS_FILE_KEYS: [&str; 3] = [
+    "aws_access_key_id",
+    "aws_secret_access_key",
+    "aws_session_token",
+];
+
+// ~/.aws/config and ~/.aws/credentials, loaded together so that edits to a
+// profile keep both files consistent
+struct ProfileFiles {
+    config_path: PathBuf,
+    config: ini::Document,
+    credentials_path: PathBuf,
+    credentials: ini::Document,
+}
+
+impl ProfileFiles {
+    fn load() -> Result<Self> {
+        let config_path = get_aws_config_path()?;
+        let credentials_path = get_aws_credentials_path()?;
+
+        Ok(ProfileFiles {
+            config: ini::Document::load(&config_path)?,
+            credentials: ini::Document::load(&credentials_path)?,
+            config_path,
+            credentials_path,
+        })
+    }
+
+    fn save(&self) -> Result<()> {
+        self.config.save(&self.config_path)?;
+
+        // Don't create a credentials file that would stay empty
+        if self.credentials_path.exists() || !self.credentials.section_names().is_empty() {
+            self.credentials.save(&self.credentials_path)?;
+        }
+
+        Ok(())
+    }
+
+    fn exists(&self, profile_name: &str) -> bool {
+        self.config.has_section(&profile_section_name(profile_name))
+            || self.credentials.has_section(profile_name)
+    }
+
+    // Settings from both files, config first; secrets are included
+    fn entries(&self, profile_name: &str) -> Vec<(String, String)> {
+        let mut entries = self.config.entries(&profile_section_name(profile_name));
+        entries.extend(self.credentials.entries(profile_name));
+        entries
+    }
+
+    fn set(&mut self, profile_name: &str, key: &str, value: &str) {
+        if CREDENTIALS_FILE_KEYS.contains(&key) {
+            self.credentials.set(profile_name, key, value);
+        } else {
+            self.config
+                .set(&profile_section_name(profile_name), key, value);
+        }
+    }
+
+    fn remove(&mut self, profile_name: &str, key: &str) -> bool {
+        if CREDENTIALS_FILE_KEYS.contains(&key) {
+            self.credentials.remove(profile_name, key)
+        } else {
+            self.config.remove(&profile_section_name(profile_name), key)
+        }
+    }
+
+    fn validate_new_name(&self, profile_name: &str) -> Result<()> {
+        if profile_name.is_empty() {
+            return Err(anyhow!("Profile name cannot be empty"));
+        }
+        if profile_name
+            .chars()
+            .any(|c| c.is_whitespace() || c == '[' || c == ']')
+        {
+            return Err(anyhow!(
+                "Profile name cannot contain whitespace or square brackets"
+            ));
+        }
+        if self.exists(profile_name) {
+            return Err(anyhow!("Profile '{}' already exists", profile_name));
+        }
+        Ok(())
+    }
+}
+
+// Brings the profile's okta.yaml entry in line with its config section after
+// an edit: written for Okta profiles, dropped for anything else
+fn sync_okta_yaml(profile_name: &str) -> Result<()> {
+    let profile = parse_aws_config()?
+        .into_iter()
+        .find(|p| p.name == profile_name);
+
+    match profile.map(|profile| profile.kind) {
+        Some(ProfileKind::Okta(okta)) => create_okta_yaml(profile_name, &okta),
+        _ => {
+            let mut config = load_okta_yaml()?;
+            if config.awscli.profiles.remove(profile_name).is_some() {
+                save_okta_yaml(&config)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn edit_profile(profile_name: &str, set: &[String], unset: &[String]) -> Result<()> {
+    let mut files = ProfileFiles::load()?;
+    if !files.exists(profile_name) {
+        return Err(anyhow!("Profile '{}' not found", profile_name));
+    }
+
+    if set.is_empty() && unset.is_empty() {
+        if !edit_profile_interactively(profile_name, &mut files)? {
+            println!("{}", "Cancelled, nothing was changed.".dimmed());
+            return Ok(());
+        }
+    } else {
+        for assignment in set {
+            let (key, value) = assignment
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected KEY=VALUE, got '{}'", assignment))?;
+            files.set(profile_name, key.trim(), value.trim());
+        }
+        for key in unset {
+            if !files.remove(profile_name, key) {
+                println!(
+                    "{}",
+                    format!("Profile '{}' has no setting '{}'", profile_name, key).yellow()
+                );
+            }
+        }
+    }
+
+    files.save()?;
+    sync_okta_yaml(profile_name)?;
+
+    println!(
+        "{}",
+        format!("✓ Updated profile '{}'", profile_name).green()
+    );
+    Ok(())
+}
+
+// Lets the user change, add and remove settings one at a time; returns
+// whether the changes should be saved
+fn edit_profile_interactively(profile_name: &str, files: &mut ProfileFiles) -> Result<bool> {
+    println!();
+    println!(
+        "{}",
+        format!("Edit Profile '{}'", profile_name).bold().green()
+    );
+    println!();
+
+    loop {
+        let entries = files.entries(profile_name);
+
+        let mut options: Vec<String> = entries
+            .iter()
+            .map(|(key, value)| {
+                if key == "aws_secret_access_key" || key == "aws_session_token" {
+                    format!("   {} = ********", key)
+                } else {
+                    // Nested sub-sections such as s3 span several lines
+                    format!("   {} = {}", key, value.trim().replace('\n', "; "))
+                }
+            })
+            .collect();
+        options.push("➕ Add a setting".to_string());
+        options.push("✓ Save and exit".to_string());
+
+        let Ok(selection) = Select::new("Select a setting to change:", options)
+            .with_page_size(15)
+            .raw_prompt()
+        else {
+            return Ok(false);
+        };
+
+        if selection.index == entries.len() + 1 {
+            return Ok(true);
+        }
+
+        let (key, current_value) = match entries.get(selection.index) {
+            Some((key, value)) => (key.clone(), value.clone()),
+            None => {
+                let key = Text::new("Setting name:")
+                    .with_help_message("e.g. region, output, mfa_serial")
+                    .prompt()
+                    .context("Failed to get setting name")?
+                    .trim()
+                    .to_string();
+                if key.is_empty() {
+                    continue;
+                }
+                (key, String::new())
+            }
+        };
+
+        // Nested sub-sections such as s3 span several lines and would be
+        // flattened by a single-line prompt, so they can only be removed here
+        if current_value.contains('\n') {
+            let remove = Confirm::new(&format!("Remove '{}'?", key))
+                .with_default(false)
+                .with_help_message(&format!(
+                    "Nested settings span several lines; edit them in {}",
+                    get_aws_config_path()?.display()
+                ))
+                .prompt()
+                .unwrap_or(false);
+            if remove {
+                files.remove(profile_name, &key);
+            }
+            continue;
+        }
+
+        let value = Text::new(&format!("{}:", key))
+            .with_initial_value(&current_value)
+            .with_help_message("Leave empty to remove the setting")
+            .prompt()
+            .context("Failed to get setting value")?
+            .trim()
+            .to_string();
+
+        if value == current_value.trim() {
+            continue;
+        }
+
+        if value.is_empty() {
+            files.remove(profile_name, &key);
+        } else {
+            files.set(profile_name, &key, &value);
+        }
+    }
+}
+
+fn remove_profile(profile_name: &str, yes: bool) -> Result<()> {
+    let mut files = ProfileFiles::load()?;
+    if !files.exists(profile_name) {
+        return Err(anyhow!("Profile '{}' not found", profile_name));
+    }
+
+    if !yes {
+        let confirmed = Confirm::new(&format!("Delete profile '{}'?", profile_name))
+            .with_default(false)
+            .with_help_message(&format!(
+                "Removes it from {}, {} and {}",
+                get_aws_config_path()?.display(),
+                get_aws_credentials_path()?.display(),
+                get_okta_config_path()?.display()
+            ))
+            .prompt()
+            .unwrap_or(false);
+        if !confirmed {
+            println!("{}", "Cancelled.".dimmed());
+            return Ok(());
+        }
+    }
+
+    files
+        .config
+        .remove_section(&profile_section_name(profile_name));
+    files.credentials.remove_section(profile_name);
+    files.save()?;
+
+    let mut okta_config = load_okta_yaml()?;
+    if okta_config.awscli.profiles.remove(profile_name).is_some() {
+        save_okta_yaml(&okta_config)?;
+    }
+
+    println!(
+        "{}",
+        format!("✓ Deleted profile '{}'", profile_name).green()
+    );
+
+    // Role chains that started from this profile no longer resolve
+    let dependents: Vec<String> = parse_aws_config()?
+        .into_iter()
+        .filter(|p| p.source_profile() == Some(profile_name))
+        .map(|p| p.name)
+        .collect();
+    if !dependents.is_empty() {
+        println!(
+            "{}",
+            format!(
+                "Profiles still using '{}' as source_profile: {}",
+                profile_name,
+                dependents.join(", ")
+            )
+            .yellow()
+        );
+    }
+
+    Ok(())
+}
+
+fn rename_profile(profile_name: &str, new_name: &str) -> Result<()> {
+    let mut files = ProfileFiles::load()?;
+    if !files.exists(profile_name) {
+        return Err(anyhow!("Profile '{}' not found", profile_name));
+    }
+    files.validate_new_name(new_name)?;
+
+    files.config.rename_section(
+        &profile_section_name(profile_name),
+        &profile_section_name(new_name),
+    );
+    files.credentials.rename_section(profile_name, new_name);
+
+    // Keep role chains pointing at the renamed profile
+    let mut dependents = Vec::new();
+    for section in files.config.section_names() {
+        if files.config.get(&section, "source_profile") == Some(profile_name) {
+            files.config.set(&section, "source_profile", new_name);
+            dependents.push(section.trim_start_matches("profile ").to_string());
+        }
+    }
+
+    files.save()?;
+
+    let mut okta_config = load_okta_yaml()?;
+    if let Some(okta_profile) = okta_config.awscli.profiles.remove(profile_name) {
+        okta_config
+            .awscli
+            .profiles
+            .insert(new_name.to_string(), okta_profile);
+        save_okta_yaml(&okta_config)?;
+    }
+
+    println!(
+        "{}",
+        format!("✓ Renamed profile '{}' to '{}'", profile_name, new_name).green()
+    );
+    if !dependents.is_empty() {
+        println!(
+            "{}",
+            format!("  Updated source_profile in: {}", dependents.join(", ")).dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+fn copy_profile(profile_name: &str, new_name: &str) -> Result<()> {
+    let mut files = ProfileFiles::load()?;
+    if !files.exists(profile_name) {
+        return Err(anyhow!("Profile '{}' not found", profile_name));
+    }
+    files.validate_new_name(new_name)?;
+
+    files.config.copy_section(
+        &profile_section_name(profile_name),
+        &profile_section_name(new_name),
+    );
+    files.credentials.copy_section(profile_name, new_name);
+    files.save()?;
+
+    let mut okta_config = load_okta_yaml()?;
+    if let Some(okta_profile) = okta_config.awscli.profiles.get(profile_name).cloned() {
+        okta_config
+            .awscli
+            .profiles
+            .insert(new_name.to_string(), okta_profile);
+        save_okta_yaml(&okta_config)?;
+    }
+
+    println!(
+        "{}",
+        format!("✓ Copied profile '{}' to '{}'", profile_name, new_name).green()
+    );
+    Ok(())
+}
+
+fn choose_profile(message: &str, profiles: &[Profile]) -> Result<String> {
+    let names: Vec<String> = profiles.iter().map(|p| p.name.clone()).collect();
+    Select::new(message, names)
+        .with_page_size(10)
+        .prompt()
+        .context("Failed to select profile")
+}
+
+fn prompt_new_profile_name(message: &str) -> Result<String> {
+    Ok(Text::new(message)
+        .prompt()
+        .context("Failed to get profile name")?
+        .trim()
+        .to_string())
+}
+
+// Not #[tokio::main]: the file overrides are exported before the runtime
+// starts its worker threads, as changing the environment while other threads
+// may read it is unsound
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    // Exported so that the SDK, okta-aws-cli and session shells read the same
+    // files as aaa; made absolute as shells may change directory
+    for (path, env_var) in [
+        (&cli.config_file, CONFIG_FILE_ENV),
+        (&cli.credentials_file, CREDENTIALS_FILE_ENV),
+        (&cli.okta_config, OKTA_CONFIG_ENV),
+    ] {
+        if let Some(path) = path {
+            let path = std::path::absolute(path)
+                .with_context(|| format!("Invalid path {}", path.display()))?;
+            env::set_var(env_var, path);
+        }
+    }
+
+    tokio::runtime::Runtime::new()
+        .context("Failed to start the async runtime")?
+        .block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    let session_options = SessionOptions {
+        force_login: cli.force_login,
+        credentials_endpoint: cli.credentials_endpoint,
+        warn_before: cli.warn_before,
+        nested: cli.nested,
+    };
+
+    if let Some(Commands::Agent {
+        action,
+        foreground,
+        idle_timeout,
+    }) = cli.command
+    {
+        return match action {
+            Some(AgentCommand::Status) => agent::status().await,
+            Some(AgentCommand::Lock) => agent::lock().await,
+            Some(AgentCommand::Stop) => agent::stop().await,
+            None if foreground => agent::run(idle_timeout).await,
+            None => agent::start(idle_timeout).await,
+        };
+    }
+
+    // Runs on every prompt, so it must not wait on the AWS config
+    if let Some(Commands::Prompt { format }) = &cli.command {
+        print_prompt_segment(format);
+        return Ok(());
+    }
+
+    let mut profiles = parse_aws_config().context("Failed to parse AWS config")?;
+
+    if let Some(Commands::CredentialProcess { profile, install }) = cli.command {
+        let profile = profiles
+            .iter()
+            .find(|p| p.name == profile)
+            .ok_or_else(|| anyhow!("Profile '{}' not found", profile))?;
+
+        return match install {
+            Some(wrapper_name) => install_credential_process_profile(profile, &wrapper_name),
+            None => credential_process(profile).await,
+        };
+    }
+
+    match &cli.command {
+        Some(Commands::Edit {
+            profile,
+            set,
+            unset,
+        }) => return edit_profile(profile, set, unset),
+        Some(Commands::Rm { profile, yes }) => return remove_profile(profile, *yes),
+        Some(Commands::Mv { profile, new_name }) => return rename_profile(profile, new_name),
+        Some(Commands::Cp { profile, new_name }) => return copy_profile(profile, new_name),
+        _ => {}
+    }
+
+    if let Some(Commands::SsoSync { template }) = cli.command {
+        return sync_sso_profiles(&template).await;
+    }
+
+    if let Some(Commands::Exec {
+        profile,
+        force_login,
+        command,
+    }) = &cli.command
+    {
+        let profile = profiles
+            .iter()
+            .find(|p| p.name == *profile)
+            .ok_or_else(|| anyhow!("Profile '{}' not found", profile))?;
+
+        let code = exec_with_credentials(profile, command, *force_login).await?;
+        std::process::exit(code);
+    }
+
+    if let Some(Commands::Env {
+        profile,
+        format,
+        unset,
+        force_login,
+    }) = &cli.command
+    {
+        if *unset {
+            if !format.can_unset() {
+                return Err(anyhow!(
+                    "--unset only works with the bash, fish, powershell and nushell formats"
+                ));
+            }
+            print!("{}", format.script(&HashMap::new(), &SESSION_ENV_VARS));
+            return Ok(());
+        }
+
+        let profile_name = profile.as_deref().unwrap_or_default();
+        let profile = profiles
+            .iter()
+            .find(|p| p.name == profile_name)
+            .ok_or_else(|| anyhow!("Profile '{}' not found", profile_name))?;
+
+        return print_credentials_env(profile, *format, *force_login).await;
+    }
+
+    if let Some(Commands::Sessions) = cli.command {
+        list_sessions();
+        return Ok(());
+    }
+
+    if let Some(Commands::Refresh) = cli.command {
+        return refresh_session(&profiles).await;
+    }
+
+    // If profile specified via command line, use it directly
+    if let Some(profile_name) = cli.profile {
         let profile = profiles
             .iter()
             .find(|p| p.name == profile_name)
             .ok_or_else(|| anyhow!("Profile '{}' not found", profile_name))?;
 
-        authenticate_and_spawn_shell(profile).await?;
+        authenticate_and_spawn_shell(profile, &session_options).await?;
         return Ok(());
     }
 
     // Interactive mode: show menu
     loop {
-        let mut options: Vec<String> = Vec::new();
-        options.push("➕ Add a new SSO profile".to_string());
-        options.push("➕ Add a new Okta profile".to_string());
-        options.push("➕ Add a new credentials profile".to_string());
+        let mut options: Vec<String> = vec![
+            "➕ Add a new SSO profile".to_string(),
+            "➕ Add a new Okta profile".to_string(),
+            "➕ Add a new credentials profile".to_string(),
+            "➕ Add a new assume-role profile".to_string(),
+            "➕ Add a new SAML profile".to_string(),
+            "➕ Add a new web-identity profile".to_string(),
+            "➕ Add a new IAM Roles Anywhere profile".to_string(),
+            "🔄 Discover SSO accounts and roles".to_string(),
+        ];
+
+        if !profiles.is_empty() {
+            options.extend([
+                "✏️ Edit a profile".to_string(),
+                "📋 Copy a profile".to_string(),
+                "🔀 Rename a profile".to_string(),
+                "🗑️ Delete a profile".to_string(),
+            ]);
+        }
 
         for profile in &profiles {
-            let profile_type = if profile.is_okta {
-                "Okta"
-            } else if profile.is_sso {
-                "SSO"
-            } else {
-                "Standard"
-            };
-            options.push(format!("   {} [{}]", profile.name, profile_type));
+            let mut option = format!(
+                "   {} [{}] ({})",
+                profile.name,
+                profile.kind.label(),
+                profile.source.label()
+            );
+            if let Some(mismatch) = profile.mismatch() {
+                option.push_str(&format!(" ⚠️ {}", mismatch));
+            }
+            options.push(option);
         }
 
         if profiles.is_empty() {
@@ -674,7 +2107,7 @@ async fn main() -> Result<()> {
                     match create_new_sso_profile() {
                         Ok(new_profile) => {
                             profiles.push(new_profile.clone());
-                            authenticate_and_spawn_shell(&new_profile).await?;
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
                             break;
                         }
                         Err(e) => {
@@ -689,7 +2122,7 @@ async fn main() -> Result<()> {
                     match create_new_okta_profile() {
                         Ok(new_profile) => {
                             profiles.push(new_profile.clone());
-                            authenticate_and_spawn_shell(&new_profile).await?;
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
                             break;
                         }
                         Err(e) => {
@@ -704,7 +2137,7 @@ async fn main() -> Result<()> {
                     match create_new_credentials_profile() {
                         Ok(new_profile) => {
                             profiles.push(new_profile.clone());
-                            authenticate_and_spawn_shell(&new_profile).await?;
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
                             break;
                         }
                         Err(e) => {
@@ -714,6 +2147,103 @@ async fn main() -> Result<()> {
                             continue;
                         }
                     }
+                } else if choice.starts_with("➕ Add a new assume-role profile") {
+                    // Create new assume-role profile
+                    match create_new_assume_role_profile() {
+                        Ok(new_profile) => {
+                            profiles.push(new_profile.clone());
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
+                            break;
+                        }
+                        Err(e) => {
+                            println!();
+                            println!("{} {}", "Error creating profile:".red(), e);
+                            println!();
+                            continue;
+                        }
+                    }
+                } else if choice.starts_with("➕ Add a new SAML profile") {
+                    // Create new SAML profile
+                    match create_new_saml_profile() {
+                        Ok(new_profile) => {
+                            profiles.push(new_profile.clone());
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
+                            break;
+                        }
+                        Err(e) => {
+                            println!();
+                            println!("{} {}", "Error creating profile:".red(), e);
+                            println!();
+                            continue;
+                        }
+                    }
+                } else if choice.starts_with("➕ Add a new web-identity profile") {
+                    // Create new web-identity profile
+                    match create_new_web_identity_profile() {
+                        Ok(new_profile) => {
+                            profiles.push(new_profile.clone());
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
+                            break;
+                        }
+                        Err(e) => {
+                            println!();
+                            println!("{} {}", "Error creating profile:".red(), e);
+                            println!();
+                            continue;
+                        }
+                    }
+                } else if choice.starts_with("➕ Add a new IAM Roles Anywhere profile") {
+                    // Create new IAM Roles Anywhere profile
+                    match create_new_roles_anywhere_profile() {
+                        Ok(new_profile) => {
+                            profiles.push(new_profile.clone());
+                            authenticate_and_spawn_shell(&new_profile, &session_options).await?;
+                            break;
+                        }
+                        Err(e) => {
+                            println!();
+                            println!("{} {}", "Error creating profile:".red(), e);
+                            println!();
+                            continue;
+                        }
+                    }
+                } else if choice.starts_with("🔄 Discover SSO accounts and roles") {
+                    // Generate profiles from the SSO account/role list
+                    if let Err(e) = sync_sso_profiles(DEFAULT_PROFILE_NAME_TEMPLATE).await {
+                        println!();
+                        println!("{} {}", "Error discovering SSO profiles:".red(), e);
+                        println!();
+                    }
+                    profiles = parse_aws_config().context("Failed to parse AWS config")?;
+                    continue;
+                } else if choice.starts_with("✏️ Edit a profile")
+                    || choice.starts_with("📋 Copy a profile")
+                    || choice.starts_with("🔀 Rename a profile")
+                    || choice.starts_with("🗑️ Delete a profile")
+                {
+                    let result = if choice.starts_with("✏️") {
+                        choose_profile("Profile to edit:", &profiles)
+                            .and_then(|name| edit_profile(&name, &[], &[]))
+                    } else if choice.starts_with("📋") {
+                        choose_profile("Profile to copy:", &profiles).and_then(|name| {
+                            copy_profile(&name, &prompt_new_profile_name("Name of the copy:")?)
+                        })
+                    } else if choice.starts_with("🔀") {
+                        choose_profile("Profile to rename:", &profiles).and_then(|name| {
+                            rename_profile(&name, &prompt_new_profile_name("New profile name:")?)
+                        })
+                    } else {
+                        choose_profile("Profile to delete:", &profiles)
+                            .and_then(|name| remove_profile(&name, false))
+                    };
+
+                    if let Err(e) = result {
+                        println!();
+                        println!("{} {}", "Error:".red(), e);
+                    }
+                    println!();
+                    profiles = parse_aws_config().context("Failed to parse AWS config")?;
+                    continue;
                 } else {
                     // Extract profile name from selection (remove leading spaces and type indicator)
                     let profile_name = choice
@@ -732,7 +2262,7 @@ async fn main() -> Result<()> {
                     }
 
                     if let Some(profile) = profiles.iter().find(|p| p.name == profile_name) {
-                        authenticate_and_spawn_shell(profile).await?;
+                        authenticate_and_spawn_shell(profile, &session_options).await?;
                         break;
                     } else {
                         println!();
@@ -753,7 +2283,7 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-async fn authenticate_and_spawn_shell(profile: &Profile) -> Result<()> {
+async fn authenticate_and_spawn_shell(profile: &Profile, options: &SessionOptions) -> Result<()> {
     println!();
     println!(
         "{} {}",
@@ -762,59 +2292,527 @@ async fn authenticate_and_spawn_shell(profile: &Profile) -> Result<()> {
     );
     println!();
 
-    // Set AWS_PROFILE environment variable
-    env::set_var("AWS_PROFILE", &profile.name);
+    // Decide up front whether a session we run inside of goes away
+    let mut parent_chain = session::chain();
+    let mut closed_outer = None;
+    if let Some(&current_pid) = parent_chain.last() {
+        let current = session::load(&session::state_path(current_pid)?)?;
+        if current.shell_pid == Some(std::process::id()) {
+            // Started with `exec aaa` from the session's shell, which is gone
+            // now: this session takes its place
+            println!(
+                "{}",
+                format!("Replacing the '{}' session.", current.profile).dimmed()
+            );
+            session::close(&current)?;
+            parent_chain.pop();
+        } else {
+            match choose_nested_action(profile, &current, options.nested)? {
+                NestedAction::Abort => {
+                    println!("{}", "Cancelled.".dimmed());
+                    return Ok(());
+                }
+                NestedAction::Nest => {}
+                NestedAction::CloseOuter => {
+                    parent_chain.pop();
+                    closed_outer = Some(current);
+                }
+            }
+        }
+    }
+
+    let credentials = session_credentials(profile, options.force_login).await?;
 
-    if profile.is_okta {
+    if options.credentials_endpoint {
+        // The endpoint lives as long as the shell and refreshes in the background
+        let expiration = credentials
+            .expiry()
+            .map(chrono::DateTime::<chrono::Utc>::from);
+        let endpoint = credentials_endpoint::start(profile, credentials).await?;
+
+        println!();
+        println!("{}", "✓ Credentials obtained successfully!".green().bold());
         println!(
             "{}",
-            "This is an Okta profile. Initiating Okta authentication...".yellow()
+            format!("Serving credentials at {}", endpoint.full_uri).dimmed()
         );
-        okta_login(profile).await?;
-    } else if profile.is_sso {
+        println!();
+
+        let env_vars = get_endpoint_env(profile, &endpoint);
+        let result = tokio::task::block_in_place(|| {
+            spawn_shell_with_credentials(
+                profile,
+                env_vars,
+                expiration,
+                Some(&endpoint.full_uri),
+                options,
+                &parent_chain,
+            )
+        });
+
+        return close_outer_session(closed_outer, result);
+    }
+
+    // Export credentials to the environment
+    let env_vars = get_credentials_env(profile, &credentials);
+
+    println!();
+    println!("{}", "✓ Credentials obtained successfully!".green().bold());
+    println!();
+
+    // Spawn new shell with credentials
+    let expiration = credentials
+        .expiry()
+        .map(chrono::DateTime::<chrono::Utc>::from);
+    let result =
+        spawn_shell_with_credentials(profile, env_vars, expiration, None, options, &parent_chain);
+
+    close_outer_session(closed_outer, result)
+}
+
+fn choose_nested_action(
+    profile: &Profile,
+    current: &session::SessionState,
+    preset: Option<NestedAction>,
+) -> Result<NestedAction> {
+    if let Some(action) = preset {
+        return Ok(action);
+    }
+
+    println!(
+        "{}",
+        format!(
+            "You are already in an aaa session for '{}' (depth {}).",
+            current.profile, current.depth
+        )
+        .yellow()
+    );
+    // aaa runs as a child of the current shell and cannot put itself in that
+    // shell's place; only the shell can, by exec-ing aaa
+    if cfg!(unix) {
         println!(
             "{}",
-            "This is an SSO profile. Initiating SSO login...".yellow()
+            format!(
+                "aaa cannot replace the shell it was started from. To switch sessions in place, run: exec aaa {}",
+                shell::posix_quote(&profile.name)
+            )
+            .dimmed()
         );
-        sso_login(profile).await?;
-    } else {
+    }
+
+    let mut actions = Vec::new();
+    let mut labels = Vec::new();
+    // Closing hangs up the current shell, which needs its pid and Unix signals
+    if cfg!(unix) && current.shell_pid.is_some() {
+        actions.push(NestedAction::CloseOuter);
+        labels.push(format!(
+            "Nest '{}' and close the '{}' session when it exits",
+            profile.name, current.profile
+        ));
+    }
+    actions.push(NestedAction::Nest);
+    labels.push(format!(
+        "Nest '{}' inside the '{}' session",
+        profile.name, current.profile
+    ));
+    actions.push(NestedAction::Abort);
+    labels.push("Abort".to_string());
+
+    let selection = Select::new("What do you want to do?", labels)
+        .raw_prompt()
+        .context("Already in an aaa session; choose what to do with --nested")?;
+
+    Ok(actions[selection.index])
+}
+
+// The outer session keeps running underneath until the new shell exits; only
+// then is it ended as well, so the user lands in the shell it was started from
+fn close_outer_session(outer: Option<session::SessionState>, result: Result<()>) -> Result<()> {
+    if let Some(outer) = outer {
+        session::hang_up(&outer)?;
+    }
+    result
+}
+
+// Prints the sessions the current shell is nested in, outermost first
+fn list_sessions() {
+    let chain = session::chain();
+    if chain.is_empty() {
+        println!("{}", "Not inside an aaa session".dimmed());
+        return;
+    }
+
+    println!("{}", "aaa sessions (outermost first):".bold());
+    for (index, pid) in chain.iter().enumerate() {
+        let current = index + 1 == chain.len();
+        let marker = if current { "→" } else { " " };
+
+        let Ok(state) = session::state_path(*pid).and_then(|path| session::load(&path)) else {
+            continue;
+        };
+
+        let expiry = match state.minutes_left() {
+            Some(0) => "expired".to_string(),
+            Some(minutes) => format!("{} left", format_remaining(minutes)),
+            None => "does not expire".to_string(),
+        };
+        let line = format!(
+            "{} {}. {} {}",
+            marker,
+            index + 1,
+            state.profile,
+            format!("(pid {}, {})", state.pid, expiry).dimmed()
+        );
+
+        if current {
+            println!("{}", line.green().bold());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+// Takes the profile's session from the aaa agent when it holds one, otherwise
+// logs in and hands the new credentials to the agent for other terminals
+async fn session_credentials(profile: &Profile, force_login: bool) -> Result<Credentials> {
+    if !force_login {
+        if let Some(credentials) = agent::get(&profile.name).await {
+            let expires_at = credentials
+                .expiry()
+                .map(|expiry| chrono::DateTime::<chrono::Utc>::from(expiry).to_rfc3339())
+                .unwrap_or_default();
+            println!(
+                "{}",
+                format!("✓ Using session from aaa agent (expires at {})", expires_at).green()
+            );
+            return Ok(credentials);
+        }
+    }
+
+    let credentials = match login(profile, force_login).await? {
+        // Federated logins hand back the session they obtained
+        Some(credentials) => credentials,
+        None => {
+            println!("Fetching credentials...");
+            resolve_credentials(profile, true).await?
+        }
+    };
+    agent::store(&profile.name, &credentials).await;
+
+    Ok(credentials)
+}
+
+// Renews a session without prompting, for the credentials endpoint of a running
+// shell: from the aaa agent, the cached SSO token or the stored credentials
+async fn unattended_session_credentials(profile: &Profile) -> Result<Credentials> {
+    if let Some(credentials) = agent::get(&profile.name).await {
+        return Ok(credentials);
+    }
+
+    let credentials = resolve_credentials(profile, false).await.with_context(|| {
+        format!(
+            "Cannot renew the session for '{}' without logging in; run 'aaa refresh'",
+            profile.name
+        )
+    })?;
+
+    // A stored session about to expire would be served again right away
+    let refresh_at = chrono::Utc::now() + chrono::Duration::minutes(SESSION_REFRESH_MARGIN_MINUTES);
+    if credentials
+        .expiry()
+        .is_some_and(|expiry| chrono::DateTime::<chrono::Utc>::from(expiry) <= refresh_at)
+    {
+        return Err(anyhow!(
+            "The stored session for '{}' has expired; run 'aaa refresh'",
+            profile.name
+        ));
+    }
+
+    agent::store(&profile.name, &credentials).await;
+    Ok(credentials)
+}
+
+// Makes sure the profile can produce credentials, logging in where needed;
+// returns the credentials when the login itself obtained them
+async fn login(profile: &Profile, force_login: bool) -> Result<Option<Credentials>> {
+    match &profile.kind {
+        ProfileKind::Okta(okta) => {
+            println!(
+                "{}",
+                "This is an Okta profile. Initiating Okta authentication...".yellow()
+            );
+            return okta_login(profile, okta, force_login).await;
+        }
+        ProfileKind::Saml(saml) => {
+            println!(
+                "{}",
+                "This is a SAML profile. Initiating SAML authentication...".yellow()
+            );
+            return saml_login(profile, saml, force_login).await;
+        }
+        ProfileKind::RolesAnywhere(roles_anywhere) => {
+            println!(
+                "{}",
+                "This is an IAM Roles Anywhere profile. Checking the certificate...".yellow()
+            );
+            show_roles_anywhere_certificate(roles_anywhere)?;
+        }
+        ProfileKind::WebIdentity(web_identity) => {
+            println!(
+                "{}",
+                "This is a web-identity profile. Obtaining the OIDC token...".yellow()
+            );
+            show_web_identity_token(web_identity).await?;
+        }
+        ProfileKind::Sso(sso) => {
+            println!(
+                "{}",
+                "This is an SSO profile. Initiating SSO login...".yellow()
+            );
+            sso_login(sso, force_login).await?;
+        }
+        ProfileKind::AssumeRole(assume_role) => {
+            println!(
+                "{}",
+                "This is an assume-role profile. Authenticating source credentials...".yellow()
+            );
+            match find_source_profile(profile)? {
+                Some(source) => {
+                    println!("{} {}", "Source profile:".bold(), source.name.green());
+                    Box::pin(login(&source, force_login)).await?;
+                }
+                None => {
+                    if let Some(credential_source) = &assume_role.credential_source {
+                        println!("{} {}", "Credential source:".bold(), credential_source);
+                    } else {
+                        verify_credentials(profile)?;
+                    }
+                }
+            }
+        }
+        ProfileKind::Process { command } => {
+            println!(
+                "{}",
+                format!("This profile uses credential_process: {}", command).blue()
+            );
+        }
+        ProfileKind::Static { .. } => {
+            println!(
+                "{}",
+                "This is a standard profile. Using credentials from the credentials file".blue()
+            );
+            verify_credentials(profile)?;
+        }
+    }
+
+    Ok(None)
+}
+
+// Returns the profile providing the base credentials of an assume-role profile,
+// or None when they come from a credential_source or the profile's own keys
+fn find_source_profile(profile: &Profile) -> Result<Option<Profile>> {
+    let source_name = match profile.source_profile() {
+        Some(source_name) if source_name != profile.name => source_name,
+        _ => return Ok(None),
+    };
+
+    let profiles = parse_aws_config()?;
+
+    // Walk the whole chain once to reject circular source_profile references
+    let mut chain = vec![profile.name.clone()];
+    let mut current = source_name.to_string();
+    loop {
+        if chain.contains(&current) {
+            chain.push(current);
+            return Err(anyhow!(
+                "Circular source_profile chain: {}",
+                chain.join(" -> ")
+            ));
+        }
+        chain.push(current.clone());
+
+        let next = profiles
+            .iter()
+            .find(|p| p.name == current)
+            .ok_or_else(|| anyhow!("Source profile '{}' not found", current))?;
+
+        match next.source_profile() {
+            Some(next_source) if next_source != next.name => {
+                current = next_source.to_string();
+            }
+            _ => break,
+        }
+    }
+
+    let source = profiles
+        .into_iter()
+        .find(|p| p.name == source_name)
+        .ok_or_else(|| anyhow!("Source profile '{}' not found", source_name))?;
+
+    Ok(Some(source))
+}
+
+// Reads x_security_token_expires of the session credentials stored for a profile
+fn load_session_expiry(profile_name: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    Ok(load_aws_credentials()?
+        .get(profile_name, "x_security_token_expires")
+        .and_then(|expires| chrono::DateTime::parse_from_rfc3339(expires).ok())
+        .map(|expires| expires.with_timezone(&chrono::Utc)))
+}
+
+// Skips federation when the credentials written by the last login are still good
+fn reuse_session_credentials(profile: &Profile) -> Result<bool> {
+    let refresh_at = chrono::Utc::now() + chrono::Duration::minutes(SESSION_REFRESH_MARGIN_MINUTES);
+
+    match load_session_expiry(&profile.name)? {
+        Some(expires_at) if expires_at > refresh_at => {
+            println!(
+                "{}",
+                format!(
+                    "✓ Reusing session credentials (expire at {})",
+                    expires_at.to_rfc3339()
+                )
+                .green()
+            );
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+async fn sso_login(sso: &SsoSettings, force_login: bool) -> Result<()> {
+    if !force_login {
+        if let Some(token) = sso::load_fresh_token(sso).await? {
+            println!(
+                "{}",
+                format!(
+                    "✓ Reusing cached SSO session (expires at {})",
+                    token.expires_at
+                )
+                .green()
+            );
+            return Ok(());
+        }
+    }
+
+    println!("Starting AWS SSO device authorization...");
+
+    sso::login(sso).await.context("SSO login failed")?;
+
+    println!("{}", "✓ SSO login successful!".green());
+    Ok(())
+}
+
+// Returns the new session, or None when the stored one is reused or
+// okta-aws-cli wrote it to the credentials file
+async fn okta_login(
+    profile: &Profile,
+    okta: &OktaSettings,
+    force_login: bool,
+) -> Result<Option<Credentials>> {
+    if !force_login && reuse_session_credentials(profile)? {
+        return Ok(None);
+    }
+
+    // The native flow needs the federation app; otherwise let okta-aws-cli discover it
+    if okta.federation_app_id.is_none() {
         println!(
             "{}",
-            "This is a standard profile. Using credentials from ~/.aws/credentials".blue()
+            "No AWS Account Federation App ID configured, falling back to okta-aws-cli".dimmed()
         );
-        verify_credentials(profile)?;
+        okta_cli_login(&profile.name, okta)?;
+        return Ok(None);
     }
 
-    // Get credentials and export to environment
-    let credentials = get_credentials(profile).await?;
+    println!("Starting Okta device authorization...");
 
-    println!();
-    println!("{}", "✓ Credentials obtained successfully!".green().bold());
-    println!();
+    let credentials = okta::login(okta, profile.region.as_deref())
+        .await
+        .context("Okta authentication failed")?;
+
+    store_session_credentials(&profile.name, &credentials).await?;
+
+    println!("{}", "✓ Okta authentication successful!".green());
+    Ok(Some(credentials))
+}
+
+// Returns the new session, or None when the stored one is reused
+async fn saml_login(
+    profile: &Profile,
+    saml: &SamlSettings,
+    force_login: bool,
+) -> Result<Option<Credentials>> {
+    if !force_login && reuse_session_credentials(profile)? {
+        return Ok(None);
+    }
+
+    let credentials = saml::login(saml, profile.region.as_deref())
+        .await
+        .context("SAML authentication failed")?;
+
+    store_session_credentials(&profile.name, &credentials).await?;
+
+    println!("{}", "✓ SAML authentication successful!".green());
+    Ok(Some(credentials))
+}
+
+fn show_roles_anywhere_certificate(roles_anywhere: &RolesAnywhereSettings) -> Result<()> {
+    let certificate_path = roles_anywhere
+        .certificate
+        .as_deref()
+        .ok_or_else(|| anyhow!("Certificate is required but not configured"))?;
+
+    let certificate = roles_anywhere::load_certificate(certificate_path)?;
 
-    // Spawn new shell with credentials
-    spawn_shell_with_credentials(profile, credentials)?;
+    println!("{} {}", "Subject:".bold(), certificate.subject);
+    println!("{} {}", "Serial:".bold(), certificate.serial_number);
+    println!(
+        "{} {}",
+        "Expires:".bold(),
+        certificate.not_after.to_rfc3339()
+    );
+
+    if certificate.not_after <= chrono::Utc::now() {
+        return Err(anyhow!(
+            "Certificate '{}' expired at {}",
+            certificate_path,
+            certificate.not_after.to_rfc3339()
+        ));
+    }
 
     Ok(())
 }
 
-async fn sso_login(profile: &Profile) -> Result<()> {
-    println!("Calling AWS SSO login...");
+// Prints who the OIDC token identifies; the claims are not verified locally
+async fn show_web_identity_token(web_identity: &WebIdentitySettings) -> Result<()> {
+    let token = web_identity::fetch_token(web_identity).await?;
+    let claims = web_identity::decode_claims(&token)?;
 
-    let output = Command::new("aws")
-        .args(["sso", "login", "--profile", &profile.name])
-        .status()
-        .context("Failed to execute 'aws sso login'")?;
-
-    if !output.success() {
-        return Err(anyhow!("SSO login failed"));
+    println!(
+        "{} {}",
+        "Issuer:".bold(),
+        claims.iss.as_deref().unwrap_or("unknown")
+    );
+    println!(
+        "{} {}",
+        "Subject:".bold(),
+        claims.sub.as_deref().unwrap_or("unknown")
+    );
+    if let Some(expires_at) = claims.expires_at() {
+        println!("{} {}", "Expires:".bold(), expires_at.to_rfc3339());
+
+        if expires_at <= chrono::Utc::now() {
+            return Err(anyhow!(
+                "Web identity token expired at {}",
+                expires_at.to_rfc3339()
+            ));
+        }
     }
 
-    println!("{}", "✓ SSO login successful!".green());
     Ok(())
 }
 
-async fn okta_login(profile: &Profile) -> Result<()> {
+fn okta_cli_login(profile_name: &str, okta: &OktaSettings) -> Result<()> {
     println!("Calling okta-aws-cli for authentication...");
 
     // Build the okta-aws-cli command
@@ -822,35 +2820,33 @@ async fn okta_login(profile: &Profile) -> Result<()> {
     cmd.arg("web");
 
     // Add required parameters
-    if let Some(ref org_domain) = profile.okta_org_domain {
-        cmd.args(["--org-domain", org_domain]);
-    } else {
-        return Err(anyhow!("Okta org domain is required but not configured"));
-    }
+    cmd.args(["--org-domain", &okta.org_domain]);
 
-    if let Some(ref oidc_client_id) = profile.okta_oidc_client_id {
+    if let Some(ref oidc_client_id) = okta.oidc_client_id {
         cmd.args(["--oidc-client-id", oidc_client_id]);
     } else {
         return Err(anyhow!("OIDC client ID is required but not configured"));
     }
 
     // Add optional parameters
-    if let Some(ref app_id) = profile.okta_aws_account_federation_app_id {
+    if let Some(ref app_id) = okta.federation_app_id {
         cmd.args(["--aws-acct-fed-app-id", app_id]);
     }
 
-    if let Some(ref iam_role) = profile.okta_aws_iam_role {
+    if let Some(ref iam_role) = okta.iam_role {
         cmd.args(["--aws-iam-role", iam_role]);
     }
 
-    if let Some(ref iam_idp) = profile.okta_aws_iam_idp {
+    if let Some(ref iam_idp) = okta.iam_idp {
         cmd.args(["--aws-iam-idp", iam_idp]);
     }
 
     // Set output format to AWS credentials file
     cmd.args(["--format", "aws-credentials"]);
-    cmd.args(["--profile", &profile.name]);
+    cmd.args(["--profile", profile_name]);
     cmd.arg("--write-aws-credentials");
+    cmd.arg("--aws-credentials")
+        .arg(get_aws_credentials_path()?);
 
     println!("Running okta-aws-cli web command...");
     println!(
@@ -880,40 +2876,218 @@ fn verify_credentials(profile: &Profile) -> Result<()> {
         ));
     }
 
-    let content = fs::read_to_string(&creds_path).context("Failed to read AWS credentials file")?;
-
-    let config: AwsConfig =
-        serde_ini::from_str(&content).context("Failed to parse AWS credentials file")?;
-
-    if !config.sections.contains_key(&profile.name) {
+    if !ini::Document::load(&creds_path)?.has_section(&profile.name) {
         return Err(anyhow!(
             "Profile '{}' not found in credentials file",
             profile.name
         ));
     }
 
-    println!("{}", "✓ Credentials found in ~/.aws/credentials".green());
+    println!(
+        "{}",
+        format!("✓ Credentials found in {}", creds_path.display()).green()
+    );
     Ok(())
 }
 
-async fn get_credentials(profile: &Profile) -> Result<HashMap<String, String>> {
-    use aws_config::BehaviorVersion;
+// Keys from the credentials file; session credentials written by a federated
+// login carry their expiry in x_security_token_expires
+fn load_static_credentials(profile_name: &str) -> Result<Credentials> {
+    let section = load_aws_credentials()?
+        .section(profile_name)
+        .ok_or_else(|| anyhow!("Profile '{}' not found in credentials file", profile_name))?;
+
+    let access_key_id = section
+        .get("aws_access_key_id")
+        .ok_or_else(|| anyhow!("Profile '{}' has no aws_access_key_id", profile_name))?;
+    let secret_access_key = section
+        .get("aws_secret_access_key")
+        .ok_or_else(|| anyhow!("Profile '{}' has no aws_secret_access_key", profile_name))?;
+
+    let expiry = section
+        .get("x_security_token_expires")
+        .and_then(|expires| chrono::DateTime::parse_from_rfc3339(expires).ok())
+        .map(std::time::SystemTime::from);
+
+    Ok(Credentials::new(
+        access_key_id,
+        secret_access_key,
+        section.get("aws_session_token").cloned(),
+        expiry,
+        "aaa-static",
+    ))
+}
 
-    println!("Fetching credentials...");
+fn prompt_mfa_code(mfa_serial: &str, interactive: bool) -> Result<String> {
+    if !interactive {
+        return Err(anyhow!("An MFA code for {} is required", mfa_serial));
+    }
 
-    // Load AWS config with the specified profile
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .profile_name(&profile.name)
-        .load()
-        .await;
+    let token_code = Text::new("MFA code:")
+        .with_help_message(&format!("Current 6-digit code for {}", mfa_serial))
+        .prompt()
+        .context("Failed to get MFA code")?
+        .trim()
+        .to_string();
 
-    let credentials = config
-        .credentials_provider()
-        .ok_or_else(|| anyhow!("No credentials provider available"))?
-        .provide_credentials()
-        .await
-        .context("Failed to retrieve credentials")?;
+    if token_code.len() != 6 || !token_code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow!("MFA code must be 6 digits"));
+    }
 
+    Ok(token_code)
+}
+
+async fn get_source_credentials(
+    profile: &Profile,
+    assume_role: &AssumeRoleSettings,
+    interactive: bool,
+) -> Result<Credentials> {
+    if let Some(source) = find_source_profile(profile)? {
+        return Box::pin(resolve_credentials(&source, interactive)).await;
+    }
+
+    // A profile may use itself as source_profile to assume a role with its own keys
+    if assume_role.source_profile.as_deref() == Some(profile.name.as_str()) {
+        return load_static_credentials(&profile.name);
+    }
+
+    let credentials = match assume_role.credential_source.as_deref() {
+        Some("Environment") => {
+            aws_config::environment::EnvironmentVariableCredentialsProvider::new()
+                .provide_credentials()
+                .await
+        }
+        Some("Ec2InstanceMetadata") => {
+            aws_config::imds::credentials::ImdsCredentialsProvider::builder()
+                .build()
+                .provide_credentials()
+                .await
+        }
+        Some("EcsContainer") => {
+            aws_config::ecs::EcsCredentialsProvider::builder()
+                .build()
+                .provide_credentials()
+                .await
+        }
+        Some(other) => {
+            return Err(anyhow!(
+                "Unsupported credential_source '{}' (expected one of: {})",
+                other,
+                CREDENTIAL_SOURCES.join(", ")
+            ))
+        }
+        None => {
+            return Err(anyhow!(
+                "Profile '{}' has neither source_profile nor credential_source",
+                profile.name
+            ))
+        }
+    };
+
+    credentials.context("Failed to retrieve source credentials")
+}
+
+// Without interactive, fails where an MFA code would have to be prompted for
+async fn resolve_credentials(profile: &Profile, interactive: bool) -> Result<Credentials> {
+    match &profile.kind {
+        ProfileKind::Sso(sso) => {
+            // Exchange the cached SSO token for role credentials
+            sso::get_role_credentials(&profile.name, sso).await
+        }
+        ProfileKind::RolesAnywhere(roles_anywhere) => {
+            // Sign CreateSession with the X.509 certificate and its private key
+            roles_anywhere::create_session(roles_anywhere).await
+        }
+        ProfileKind::WebIdentity(web_identity) => {
+            let duration_seconds = web_identity
+                .duration_seconds
+                .as_deref()
+                .map(str::parse::<i32>)
+                .transpose()
+                .context("duration_seconds must be a number of seconds")?;
+            let role_session_name = web_identity
+                .role_session_name
+                .clone()
+                .unwrap_or_else(sts::default_role_session_name);
+
+            // Fetch the token every time, it is rotated by whoever writes the file
+            // and CI tokens are short-lived
+            let token = web_identity::fetch_token(web_identity).await?;
+
+            sts::assume_role_with_web_identity(
+                profile.region.as_deref(),
+                &web_identity.role_arn,
+                &role_session_name,
+                &token,
+                duration_seconds,
+            )
+            .await
+        }
+        ProfileKind::AssumeRole(assume_role) => {
+            let duration_seconds = assume_role
+                .duration_seconds
+                .as_deref()
+                .map(str::parse::<i32>)
+                .transpose()
+                .context("duration_seconds must be a number of seconds")?;
+            let role_session_name = assume_role
+                .role_session_name
+                .clone()
+                .unwrap_or_else(sts::default_role_session_name);
+
+            let base_credentials =
+                get_source_credentials(profile, assume_role, interactive).await?;
+
+            let token_code = match &assume_role.mfa_serial {
+                Some(mfa_serial) => Some(prompt_mfa_code(mfa_serial, interactive)?),
+                None => None,
+            };
+
+            sts::assume_role(
+                base_credentials,
+                profile.region.as_deref(),
+                sts::AssumeRoleRequest {
+                    role_arn: &assume_role.role_arn,
+                    role_session_name: &role_session_name,
+                    external_id: assume_role.external_id.as_deref(),
+                    duration_seconds,
+                    mfa_serial: assume_role.mfa_serial.as_deref(),
+                    token_code: token_code.as_deref(),
+                },
+            )
+            .await
+        }
+        ProfileKind::Static {
+            mfa_serial: Some(mfa_serial),
+        } => {
+            // Trade the long-term keys for an MFA-authenticated session
+            let base_credentials = load_static_credentials(&profile.name)?;
+            let token_code = prompt_mfa_code(mfa_serial, interactive)?;
+
+            sts::get_session_token(
+                base_credentials,
+                profile.region.as_deref(),
+                mfa_serial,
+                &token_code,
+            )
+            .await
+        }
+        // The session the last login stored, with its expiry
+        ProfileKind::Okta(_) | ProfileKind::Saml(_) => load_static_credentials(&profile.name),
+        ProfileKind::Static { mfa_serial: None } => load_static_credentials(&profile.name),
+        ProfileKind::Process { .. } => {
+            // Read the profile files only, the environment must not shadow the profile
+            aws_config::profile::ProfileFileCredentialsProvider::builder()
+                .profile_name(&profile.name)
+                .build()
+                .provide_credentials()
+                .await
+                .context("Failed to retrieve credentials")
+        }
+    }
+}
+
+fn get_credentials_env(profile: &Profile, credentials: &Credentials) -> HashMap<String, String> {
     let mut creds_map = HashMap::new();
     creds_map.insert(
         "AWS_ACCESS_KEY_ID".to_string(),
@@ -928,6 +3102,15 @@ async fn get_credentials(profile: &Profile) -> Result<HashMap<String, String>> {
         creds_map.insert("AWS_SESSION_TOKEN".to_string(), token.to_string());
     }
 
+    if let Some(expiry) = credentials.expiry() {
+        creds_map.insert(
+            "AWS_CREDENTIAL_EXPIRATION".to_string(),
+            chrono::DateTime::<chrono::Utc>::from(expiry)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string(),
+        );
+    }
+
     if let Some(region) = &profile.region {
         creds_map.insert("AWS_REGION".to_string(), region.clone());
         creds_map.insert("AWS_DEFAULT_REGION".to_string(), region.clone());
@@ -935,12 +3118,541 @@ async fn get_credentials(profile: &Profile) -> Result<HashMap<String, String>> {
 
     creds_map.insert("AWS_PROFILE".to_string(), profile.name.clone());
 
-    Ok(creds_map)
+    // AWS_PROFILE only resolves in the files aaa was pointed at
+    for env_var in [CONFIG_FILE_ENV, CREDENTIALS_FILE_ENV] {
+        if let Ok(path) = env::var(env_var) {
+            creds_map.insert(env_var.to_string(), path);
+        }
+    }
+
+    creds_map
+}
+
+// SDKs in the shell fetch credentials from the local endpoint through the
+// container credentials provider; AWS_PROFILE is left unset so it does not win
+fn get_endpoint_env(
+    profile: &Profile,
+    endpoint: &credentials_endpoint::CredentialsEndpoint,
+) -> HashMap<String, String> {
+    let mut env_vars = HashMap::new();
+    env_vars.insert(
+        credentials_endpoint::FULL_URI_ENV.to_string(),
+        endpoint.full_uri.clone(),
+    );
+    env_vars.insert(
+        credentials_endpoint::AUTHORIZATION_TOKEN_ENV.to_string(),
+        endpoint.authorization_token.clone(),
+    );
+
+    if let Some(region) = &profile.region {
+        env_vars.insert("AWS_REGION".to_string(), region.clone());
+        env_vars.insert("AWS_DEFAULT_REGION".to_string(), region.clone());
+    }
+
+    env_vars
+}
+
+fn render_profile_name(template: &str, account_role: &sso::AccountRole) -> String {
+    let name = template
+        .replace("{account_name}", &account_role.account_name)
+        .replace("{account_id}", &account_role.account_id)
+        .replace("{role_name}", &account_role.role_name);
+
+    // Keep profile names shell- and INI-friendly
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+async fn sync_sso_profiles(template: &str) -> Result<()> {
+    println!();
+    println!("{}", "Discover AWS SSO Accounts and Roles".bold().green());
+    println!();
+
+    let session = select_sso_session()?;
+
+    let (sso_start_url, sso_region) = match &session {
+        Some(session) => (
+            session.sso_start_url.clone().unwrap_or_default(),
+            session.sso_region.clone().unwrap_or_default(),
+        ),
+        None => {
+            let sso_start_url = Text::new("SSO start URL:")
+                .with_help_message(
+                    "The AWS SSO portal URL (e.g., https://my-sso-portal.awsapps.com/start)",
+                )
+                .prompt()
+                .context("Failed to get SSO start URL")?;
+
+            let sso_region = Text::new("SSO region:")
+                .with_default("us-east-1")
+                .with_help_message("The AWS region where your SSO directory is hosted")
+                .prompt()
+                .context("Failed to get SSO region")?;
+
+            (sso_start_url, sso_region)
+        }
+    };
+
+    // SSO settings shared by every generated profile
+    let base = SsoSettings {
+        session: session.as_ref().map(|s| s.name.clone()),
+        start_url: Some(sso_start_url.clone()),
+        region: Some(sso_region.clone()),
+        registration_scopes: session
+            .as_ref()
+            .and_then(|s| s.sso_registration_scopes.clone()),
+        ..Default::default()
+    };
+    let base_name = session
+        .as_ref()
+        .map(|s| s.name.clone())
+        .unwrap_or_else(|| sso_start_url.clone());
+
+    sso_login(&base, false).await?;
+
+    println!("Listing accounts and roles...");
+    let account_roles = sso::list_account_roles(&base_name, &base).await?;
+
+    // Profiles that already point at this session or start URL
+    let existing_profiles = parse_aws_config()?;
+    let session_profiles: Vec<(&Profile, &SsoSettings)> = existing_profiles
+        .iter()
+        .filter_map(|p| match &p.kind {
+            ProfileKind::Sso(sso) => Some((p, sso)),
+            _ => None,
+        })
+        .filter(|(_, sso)| match &base.session {
+            Some(name) => sso.session.as_ref() == Some(name),
+            None => sso.session.is_none() && sso.start_url == base.start_url,
+        })
+        .collect();
+
+    let is_configured = |account_role: &sso::AccountRole| {
+        session_profiles.iter().any(|(_, sso)| {
+            sso.account_id.as_ref() == Some(&account_role.account_id)
+                && sso.role_name.as_ref() == Some(&account_role.role_name)
+        })
+    };
+
+    // Report profiles whose account or role is no longer available
+    let stale_profiles: Vec<&(&Profile, &SsoSettings)> = session_profiles
+        .iter()
+        .filter(|(_, sso)| {
+            !account_roles.iter().any(|account_role| {
+                sso.account_id.as_ref() == Some(&account_role.account_id)
+                    && sso.role_name.as_ref() == Some(&account_role.role_name)
+            })
+        })
+        .collect();
+
+    if !stale_profiles.is_empty() {
+        println!();
+        println!(
+            "{}",
+            "These profiles refer to accounts or roles that are no longer available:".yellow()
+        );
+        for (profile, sso) in &stale_profiles {
+            println!(
+                "  - {} ({} / {})",
+                profile.name,
+                sso.account_id.as_deref().unwrap_or("?"),
+                sso.role_name.as_deref().unwrap_or("?")
+            );
+        }
+        println!(
+            "{}",
+            format!(
+                "Remove them from {} if they are no longer needed.",
+                get_aws_config_path()?.display()
+            )
+            .dimmed()
+        );
+    }
+
+    if account_roles.is_empty() {
+        println!();
+        println!(
+            "{}",
+            "No accounts or roles available for this SSO user.".yellow()
+        );
+        return Ok(());
+    }
+
+    println!();
+    println!(
+        "{}",
+        format!(
+            "Found {} role(s) across {} account(s).",
+            account_roles.len(),
+            account_roles
+                .iter()
+                .map(|a| &a.account_id)
+                .collect::<HashSet<_>>()
+                .len()
+        )
+        .cyan()
+    );
+
+    let options: Vec<String> = account_roles
+        .iter()
+        .map(|account_role| {
+            format!(
+                "{} ({}) › {}{}",
+                account_role.account_name,
+                account_role.account_id,
+                account_role.role_name,
+                if is_configured(account_role) {
+                    " [configured]"
+                } else {
+                    ""
+                }
+            )
+        })
+        .collect();
+
+    let selection = MultiSelect::new("Select the account/role combinations to add:", options)
+        .with_page_size(15)
+        .raw_prompt()
+        .context("Failed to select accounts and roles")?;
+
+    let template = Text::new("Profile name template:")
+        .with_default(template)
+        .with_help_message("{account_name}, {account_id} and {role_name} are replaced")
+        .prompt()
+        .context("Failed to get profile name template")?;
+
+    let region = Text::new("Default region:")
+        .with_default("us-east-1")
+        .with_help_message("Default AWS region for the generated profiles")
+        .prompt()
+        .context("Failed to get region")?;
+
+    let mut taken_names: Vec<String> = existing_profiles.iter().map(|p| p.name.clone()).collect();
+    let mut created = 0;
+
+    for option in selection {
+        let account_role = &account_roles[option.index];
+
+        if is_configured(account_role) {
+            continue;
+        }
+
+        let profile_name = render_profile_name(&template, account_role);
+        if taken_names.contains(&profile_name) {
+            println!(
+                "{}",
+                format!("Skipping '{}': profile already exists", profile_name).yellow()
+            );
+            continue;
+        }
+
+        let profile = Profile {
+            name: profile_name.clone(),
+            kind: ProfileKind::Sso(SsoSettings {
+                account_id: Some(account_role.account_id.clone()),
+                role_name: Some(account_role.role_name.clone()),
+                ..base.clone()
+            }),
+            region: Some(region.clone()),
+            extra: Vec::new(),
+            source: ProfileSource::Config,
+        };
+
+        save_profile_to_config(&profile)?;
+        println!(
+            "{}",
+            format!("✓ Created profile '{}'", profile_name).green()
+        );
+
+        taken_names.push(profile_name);
+        created += 1;
+    }
+
+    println!();
+    println!(
+        "{}",
+        format!("✓ {} profile(s) created", created).green().bold()
+    );
+    println!();
+
+    Ok(())
+}
+
+// Renews the credentials of the aaa shell this runs in; the shell loads them
+// from the session env file
+async fn refresh_session(profiles: &[Profile]) -> Result<()> {
+    let state_path = env::var_os(session::STATE_FILE_ENV)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            anyhow!(
+                "Not inside an aaa shell ({} is not set)",
+                session::STATE_FILE_ENV
+            )
+        })?;
+    let env_path = env::var_os(session::ENV_FILE_ENV)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            anyhow!(
+                "Not inside an aaa shell ({} is not set)",
+                session::ENV_FILE_ENV
+            )
+        })?;
+
+    let mut state = session::load(&state_path)?;
+    let Some(current_expiration) = state.expiration else {
+        println!("{}", "The credentials of this shell do not expire".dimmed());
+        return Ok(());
+    };
+
+    let profile = profiles
+        .iter()
+        .find(|p| p.name == state.profile)
+        .ok_or_else(|| anyhow!("Profile '{}' not found", state.profile))?;
+
+    println!(
+        "{} {}",
+        "Refreshing profile:".bold(),
+        profile.name.green().bold()
+    );
+
+    // Cached sessions may be the ones this shell already has, log in again then
+    let mut credentials = session_credentials(profile, false).await?;
+    let renewed = |credentials: &Credentials| {
+        credentials
+            .expiry()
+            .map(chrono::DateTime::<chrono::Utc>::from)
+            .is_some_and(|expiration| expiration > current_expiration)
+    };
+    if !renewed(&credentials) {
+        credentials = session_credentials(profile, true).await?;
+    }
+
+    let expiration = credentials
+        .expiry()
+        .map(chrono::DateTime::<chrono::Utc>::from);
+
+    // An endpoint serves them right away and records the new expiry itself
+    let shell_kind = shell::Shell::from_env();
+    if let Some(full_uri) = &state.credentials_endpoint {
+        credentials_endpoint::replace(full_uri, &credentials).await?;
+    } else {
+        let env_vars = get_credentials_env(profile, &credentials);
+        session::write_env(&env_path, shell_kind, &env_vars)?;
+
+        state.expiration = expiration;
+        session::save(&state_path, &state)?;
+    }
+
+    println!();
+    println!(
+        "{}",
+        format!(
+            "✓ Credentials refreshed (expire at {})",
+            expiration
+                .map(|expiration| expiration.to_rfc3339())
+                .unwrap_or_default()
+        )
+        .green()
+        .bold()
+    );
+    if state.credentials_endpoint.is_some() {
+        println!(
+            "{}",
+            "The shell's credentials endpoint serves them from now on".dimmed()
+        );
+    } else if shell_kind.reloads_env() {
+        println!("{}", "They are loaded at the next prompt".dimmed());
+    } else {
+        println!(
+            "{}",
+            format!("Load them with: . \"${}\"", session::ENV_FILE_ENV).dimmed()
+        );
+    }
+
+    Ok(())
+}
+
+fn print_prompt_segment(template: &str) {
+    let Some(state) = env::var_os(session::STATE_FILE_ENV)
+        .and_then(|path| session::load(&PathBuf::from(path)).ok())
+    else {
+        return;
+    };
+
+    let remaining = state
+        .minutes_left()
+        .map(format_remaining)
+        .unwrap_or_default();
+
+    let segment = template
+        .replace("{profile}", &state.profile)
+        .replace("{region}", state.region.as_deref().unwrap_or_default())
+        .replace("{remaining}", &remaining);
+
+    println!("{}", segment.trim());
+}
+
+// Compact time left for prompts, e.g. "2d03h", "1h05m" or "expired"
+fn format_remaining(minutes: i64) -> String {
+    match minutes {
+        0 => "expired".to_string(),
+        minutes if minutes >= 24 * 60 => {
+            format!("{}d{:02}h", minutes / (24 * 60), minutes % (24 * 60) / 60)
+        }
+        minutes if minutes >= 60 => format!("{}h{:02}m", minutes / 60, minutes % 60),
+        minutes => format!("{}m", minutes),
+    }
+}
+
+// Prints credentials as credential_process JSON; stdout must carry nothing else
+async fn credential_process(profile: &Profile) -> Result<()> {
+    // Logging in may be needed first, its output goes to stderr
+    let credentials = {
+        let _stdout = output::StdoutToStderr::new()?;
+        session_credentials(profile, false).await?
+    };
+
+    let output = CredentialProcessOutput {
+        version: 1,
+        access_key_id: credentials.access_key_id().to_string(),
+        secret_access_key: credentials.secret_access_key().to_string(),
+        session_token: credentials.session_token().map(String::from),
+        expiration: credentials.expiry().map(|expiry| {
+            chrono::DateTime::<chrono::Utc>::from(expiry)
+                .format("%Y-%m-%dT%H:%M:%SZ")
+                .to_string()
+        }),
+    };
+
+    let json = serde_json::to_string_pretty(&output)
+        .context("Failed to serialize credential_process output")?;
+    println!("{}", json);
+
+    Ok(())
+}
+
+fn install_credential_process_profile(profile: &Profile, wrapper_name: &str) -> Result<()> {
+    // Check if profile already exists
+    let existing_profiles = parse_aws_config()?;
+    if existing_profiles.iter().any(|p| p.name == wrapper_name) {
+        return Err(anyhow!("Profile '{}' already exists", wrapper_name));
+    }
+
+    let wrapper = Profile {
+        name: wrapper_name.to_string(),
+        kind: ProfileKind::Process {
+            command: format!("aaa credential-process {}", profile.name),
+        },
+        region: profile.region.clone(),
+        extra: Vec::new(),
+        source: ProfileSource::Config,
+    };
+
+    save_profile_to_config(&wrapper)?;
+
+    println!(
+        "{}",
+        format!(
+            "✓ Created profile '{}' using credentials from '{}' via aaa",
+            wrapper_name, profile.name
+        )
+        .green()
+    );
+
+    Ok(())
+}
+
+// Session variables this session does not set itself, which depends on the
+// profile kind (static keys have no token) and the endpoint mode
+fn stale_session_env_vars(env_vars: &HashMap<String, String>) -> Vec<&'static str> {
+    SESSION_ENV_VARS
+        .into_iter()
+        .filter(|key| !env_vars.contains_key(*key))
+        .collect()
+}
+
+// Spawned shells also drop credentials inherited from other tools; the user's
+// own shell keeps them, since `aaa env` only manages what it set
+fn stale_env_vars(env_vars: &HashMap<String, String>) -> Vec<&'static str> {
+    let mut stale = stale_session_env_vars(env_vars);
+    stale.extend(
+        FOREIGN_CREDENTIAL_ENV_VARS
+            .into_iter()
+            .filter(|key| !env_vars.contains_key(*key)),
+    );
+    stale
+}
+
+fn apply_session_env(command: &mut Command, env_vars: &HashMap<String, String>) {
+    // Variables not set here must not leak in from the parent and mix identities
+    for key in stale_env_vars(env_vars) {
+        command.env_remove(key);
+    }
+
+    for (key, value) in env_vars {
+        command.env(key, value);
+    }
+}
+
+// Prints the profile's variables for `eval` and friends; variables of a
+// previous session that this one does not set are cleared first
+async fn print_credentials_env(
+    profile: &Profile,
+    format: shell::EnvFormat,
+    force_login: bool,
+) -> Result<()> {
+    // stdout carries nothing but the statements
+    let credentials = {
+        let _stdout = output::StdoutToStderr::new()?;
+        session_credentials(profile, force_login).await?
+    };
+    let env_vars = get_credentials_env(profile, &credentials);
+
+    print!(
+        "{}",
+        format.script(&env_vars, &stale_session_env_vars(&env_vars))
+    );
+    Ok(())
+}
+
+// Runs a single command with the profile's credentials instead of a shell
+async fn exec_with_credentials(
+    profile: &Profile,
+    command_line: &[String],
+    force_login: bool,
+) -> Result<i32> {
+    let (program, args) = command_line
+        .split_first()
+        .ok_or_else(|| anyhow!("No command given"))?;
+
+    // Keep the command's stdout free of login output
+    let credentials = {
+        let _stdout = output::StdoutToStderr::new()?;
+        session_credentials(profile, force_login).await?
+    };
+    let env_vars = get_credentials_env(profile, &credentials);
+
+    let mut command = Command::new(program);
+    command.args(args);
+    apply_session_env(&mut command, &env_vars);
+
+    exec::run(command).await
 }
 
 fn spawn_shell_with_credentials(
     profile: &Profile,
     credentials: HashMap<String, String>,
+    expiration: Option<chrono::DateTime<chrono::Utc>>,
+    credentials_endpoint: Option<&str>,
+    options: &SessionOptions,
+    parent_chain: &[u32],
 ) -> Result<()> {
     let shell = env::var("SHELL").unwrap_or_else(|_| "/bin/bash".to_string());
 
@@ -951,13 +3663,24 @@ fn spawn_shell_with_credentials(
     println!("{}", format!("Shell: {}", shell).dimmed());
     println!();
     println!("{}", "Environment variables set:".dimmed());
-    println!("{}", "  - AWS_ACCESS_KEY_ID".dimmed());
-    println!("{}", "  - AWS_SECRET_ACCESS_KEY".dimmed());
-    if credentials.contains_key("AWS_SESSION_TOKEN") {
-        println!("{}", "  - AWS_SESSION_TOKEN".dimmed());
+    for key in SESSION_ENV_VARS {
+        if key != "AWS_DEFAULT_REGION" && credentials.contains_key(key) {
+            println!("{}", format!("  - {}", key).dimmed());
+        }
+    }
+
+    let removed: Vec<&str> = stale_env_vars(&credentials)
+        .into_iter()
+        .filter(|key| env::var_os(key).is_some())
+        .collect();
+    if !removed.is_empty() {
+        println!();
+        println!("{}", "Inherited variables removed:".dimmed());
+        for key in removed {
+            println!("{}", format!("  - {}", key).dimmed());
+        }
     }
-    println!("{}", "  - AWS_REGION".dimmed());
-    println!("{}", "  - AWS_PROFILE".dimmed());
+
     println!();
     println!(
         "{}",
@@ -965,12 +3688,11 @@ fn spawn_shell_with_credentials(
     );
     println!();
 
+    let shell_kind = shell::Shell::detect(&shell);
     let mut command = Command::new(&shell);
 
     // Set AWS credentials as environment variables
-    for (key, value) in credentials {
-        command.env(key, value);
-    }
+    apply_session_env(&mut command, &credentials);
 
     // Preserve PATH and other important environment variables
     if let Ok(path) = env::var("PATH") {
@@ -983,16 +3705,51 @@ fn spawn_shell_with_credentials(
         command.env("USER", user);
     }
 
-    // Update PS1 to show we're in an AWS session
-    let ps1_prefix = format!("(aws:{}) ", profile.name);
-    if let Ok(current_ps1) = env::var("PS1") {
-        command.env("PS1", format!("{}{}", ps1_prefix, current_ps1));
-    } else {
-        command.env("PS1", format!("{}\\$ ", ps1_prefix));
+    // Prompts read the session file to show how long the credentials last;
+    // `aaa refresh` updates it and writes new credentials to the env file or
+    // hands them to the credentials endpoint
+    let session_files = session::create(&session::SessionState {
+        profile: profile.name.clone(),
+        region: profile.region.clone(),
+        expiration,
+        pid: std::process::id(),
+        parent: parent_chain.last().copied(),
+        depth: parent_chain.len() + 1,
+        shell_pid: None,
+        closed: false,
+        credentials_endpoint: credentials_endpoint.map(String::from),
+    })?;
+    command.env(session::STATE_FILE_ENV, &session_files.state_path);
+
+    // Nested aaa runs find the sessions they are started from
+    let mut chain = parent_chain.to_vec();
+    chain.push(std::process::id());
+    command.env(session::SESSION_ENV, session::chain_env(&chain));
+    command.env(session::ENV_FILE_ENV, &session_files.env_path);
+
+    // Show the profile in the prompt and reload refreshed credentials the way
+    // the shell supports
+    command.env(shell::PROFILE_ENV, &profile.name);
+    shell_kind.integrate(&mut command, &session_files.shell_dir, &profile.name)?;
+
+    let _expiry_warning = options
+        .warn_before
+        .map(|minutes| session::warn_before_expiry(session_files.state_path.clone(), minutes));
+
+    let mut child = command.spawn().context("Failed to spawn shell")?;
+
+    // A session started inside this one needs the shell's pid to close it
+    let mut state = session::load(&session_files.state_path)?;
+    state.shell_pid = Some(child.id());
+    session::save(&session_files.state_path, &state)?;
+
+    let status = child.wait().context("Failed to wait for shell")?;
+
+    // The shell was hung up by a session started inside it with close-outer
+    if session::load(&session_files.state_path).is_ok_and(|state| state.closed) {
+        return Ok(());
     }
 
-    let status = command.status().context("Failed to spawn shell")?;
-
     if !status.success() {
         return Err(anyhow!("Shell exited with error"));
     }
@@ -0,0 +1,459 @@
+use crate::profile::OktaSettings;
+use crate::saml;
+use crate::sts;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use colored::Colorize;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+const SCOPES: &str = "openid okta.apps.sso";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+const ID_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:id_token";
+const WEB_SSO_TOKEN_TYPE: &str = "urn:okta:oauth:token-type:web_sso_token";
+
+#[derive(Debug, Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: u64,
+    interval: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+// The org domain may carry an explicit scheme so a local stand-in can be used
+fn base_url(org_domain: &str) -> String {
+    if org_domain.starts_with("https://") || org_domain.starts_with("http://") {
+        org_domain.trim_end_matches('/').to_string()
+    } else {
+        format!("https://{}", org_domain.trim_end_matches('/'))
+    }
+}
+
+async fn read_error(response: reqwest::Response) -> ErrorResponse {
+    let status = response.status();
+    response
+        .json::<ErrorResponse>()
+        .await
+        .unwrap_or_else(|_| ErrorResponse {
+            error: format!("HTTP {}", status),
+            error_description: None,
+        })
+}
+
+fn okta_error(context: &str, error: ErrorResponse) -> anyhow::Error {
+    match error.error_description {
+        Some(description) => anyhow!("{}: {} ({})", context, error.error, description),
+        None => anyhow!("{}: {}", context, error.error),
+    }
+}
+
+async fn start_device_authorization(
+    http: &reqwest::Client,
+    base_url: &str,
+    client_id: &str,
+) -> Result<DeviceAuthorization> {
+    let response = http
+        .post(format!("{}/oauth2/v1/device/authorize", base_url))
+        .form(&[("client_id", client_id), ("scope", SCOPES)])
+        .send()
+        .await
+        .context("Failed to reach the Okta device authorization endpoint")?;
+
+    if !response.status().is_success() {
+        return Err(okta_error(
+            "Okta device authorization failed",
+            read_error(response).await,
+        ));
+    }
+
+    response
+        .json()
+        .await
+        .context("Failed to parse Okta device authorization response")
+}
+
+async fn poll_for_tokens(
+    http: &reqwest::Client,
+    base_url: &str,
+    client_id: &str,
+    authorization: &DeviceAuthorization,
+) -> Result<TokenResponse> {
+    let mut interval = Duration::from_secs(authorization.interval.unwrap_or(5).max(1));
+    let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+
+    loop {
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "Okta device authorization expired before it was approved"
+            ));
+        }
+
+        tokio::time::sleep(interval).await;
+
+        let response = http
+            .post(format!("{}/oauth2/v1/token", base_url))
+            .form(&[
+                ("client_id", client_id),
+                ("device_code", authorization.device_code.as_str()),
+                ("grant_type", DEVICE_CODE_GRANT_TYPE),
+            ])
+            .send()
+            .await
+            .context("Failed to reach the Okta token endpoint")?;
+
+        if response.status().is_success() {
+            return response
+                .json()
+                .await
+                .context("Failed to parse Okta token response");
+        }
+
+        let error = read_error(response).await;
+        match error.error.as_str() {
+            "authorization_pending" => continue,
+            "slow_down" => interval += Duration::from_secs(5),
+            _ => return Err(okta_error("Okta token request failed", error)),
+        }
+    }
+}
+
+async fn exchange_for_web_sso_token(
+    http: &reqwest::Client,
+    base_url: &str,
+    client_id: &str,
+    tokens: &TokenResponse,
+    federation_app_id: &str,
+) -> Result<String> {
+    let id_token = tokens
+        .id_token
+        .as_deref()
+        .ok_or_else(|| anyhow!("Okta did not return an ID token"))?;
+    let audience = format!("urn:okta:apps:{}", federation_app_id);
+
+    let response = http
+        .post(format!("{}/oauth2/v1/token", base_url))
+        .form(&[
+            ("client_id", client_id),
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("actor_token", tokens.access_token.as_str()),
+            ("actor_token_type", ACCESS_TOKEN_TYPE),
+            ("subject_token", id_token),
+            ("subject_token_type", ID_TOKEN_TYPE),
+            ("requested_token_type", WEB_SSO_TOKEN_TYPE),
+            ("audience", audience.as_str()),
+        ])
+        .send()
+        .await
+        .context("Failed to reach the Okta token endpoint")?;
+
+    if !response.status().is_success() {
+        return Err(okta_error(
+            "Okta web SSO token exchange failed",
+            read_error(response).await,
+        ));
+    }
+
+    let token: TokenResponse = response
+        .json()
+        .await
+        .context("Failed to parse Okta token exchange response")?;
+
+    Ok(token.access_token)
+}
+
+async fn fetch_saml_assertion(
+    http: &reqwest::Client,
+    base_url: &str,
+    web_sso_token: &str,
+) -> Result<String> {
+    let response = http
+        .get(format!("{}/login/token/sso", base_url))
+        .query(&[("token", web_sso_token)])
+        .send()
+        .await
+        .context("Failed to reach the Okta SSO endpoint")?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Okta SSO endpoint returned HTTP {}",
+            response.status()
+        ));
+    }
+
+    let html = response
+        .text()
+        .await
+        .context("Failed to read the Okta SSO response")?;
+
+    saml::extract_saml_response(&html)
+}
+
+pub async fn login(okta: &OktaSettings, region: Option<&str>) -> Result<Credentials> {
+    let client_id = okta
+        .oidc_client_id
+        .as_deref()
+        .ok_or_else(|| anyhow!("OIDC client ID is required but not configured"))?;
+    let federation_app_id = okta
+        .federation_app_id
+        .as_deref()
+        .ok_or_else(|| anyhow!("AWS Account Federation App ID is required but not configured"))?;
+
+    let base_url = base_url(&okta.org_domain);
+    let http = reqwest::Client::new();
+
+    let authorization = start_device_authorization(&http, &base_url, client_id).await?;
+    let verification_uri = authorization
+        .verification_uri_complete
+        .as_deref()
+        .unwrap_or(&authorization.verification_uri);
+
+    println!();
+    println!("Open the following URL to authorize this device:");
+    println!();
+    println!("  {}", verification_uri.cyan());
+    println!();
+    println!(
+        "Confirm that the code shown in the browser is: {}",
+        authorization.user_code.bold()
+    );
+    println!();
+
+    if webbrowser::open(verification_uri).is_err() {
+        println!(
+            "{}",
+            "Could not open a browser automatically, please open the URL manually".dimmed()
+        );
+    }
+
+    println!("{}", "Waiting for authorization...".dimmed());
+
+    let tokens = poll_for_tokens(&http, &base_url, client_id, &authorization).await?;
+    let web_sso_token =
+        exchange_for_web_sso_token(&http, &base_url, client_id, &tokens, federation_app_id).await?;
+    let saml_response = fetch_saml_assertion(&http, &base_url, &web_sso_token).await?;
+
+    let assertion = saml::parse_assertion(&saml_response)?;
+
+    // Narrow the roles down to the configured identity provider, if any
+    let roles: Vec<saml::SamlRole> = match &okta.iam_idp {
+        Some(idp) => assertion
+            .roles
+            .iter()
+            .filter(|role| role.principal_arn == *idp)
+            .cloned()
+            .collect(),
+        None => assertion.roles.clone(),
+    };
+    let role = saml::select_role(&roles, okta.iam_role.as_deref())?;
+
+    sts::assume_role_with_saml(
+        region,
+        &role.role_arn,
+        &role.principal_arn,
+        &assertion.encoded,
+        assertion.session_duration,
+    )
+    .await
+}
+
+// Loopback stand-in for Okta and STS, shared with the tests of the flows that
+// log in to Okta
+#[cfg(test)]
+pub mod stand_in {
+    use super::*;
+    use crate::test_support::{Received, Reply, StandIn};
+    use base64::Engine;
+    use serde_json::json;
+
+    pub const PROVIDER_ARN: &str = "arn:aws:iam::111111111111:saml-provider/okta";
+    pub const DEV_ROLE_ARN: &str = "arn:aws:iam::111111111111:role/Dev";
+    pub const OPS_ROLE_ARN: &str = "arn:aws:iam::111111111111:role/Ops";
+
+    pub fn encoded_saml_response() -> String {
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+<saml2p:Response xmlns:saml2p="urn:oasis:names:tc:SAML:2.0:protocol" xmlns:saml2="urn:oasis:names:tc:SAML:2.0:assertion">
+  <saml2:Assertion>
+    <saml2:AttributeStatement>
+      <saml2:Attribute Name="https://aws.amazon.com/SAML/Attributes/Role">
+        <saml2:AttributeValue>{provider},{dev}</saml2:AttributeValue>
+        <saml2:AttributeValue>{ops},{provider}</saml2:AttributeValue>
+      </saml2:Attribute>
+      <saml2:Attribute Name="https://aws.amazon.com/SAML/Attributes/SessionDuration">
+        <saml2:AttributeValue>3600</saml2:AttributeValue>
+      </saml2:Attribute>
+    </saml2:AttributeStatement>
+  </saml2:Assertion>
+</saml2p:Response>"#,
+            provider = PROVIDER_ARN,
+            dev = DEV_ROLE_ARN,
+            ops = OPS_ROLE_ARN,
+        );
+        base64::engine::general_purpose::STANDARD.encode(xml)
+    }
+
+    const ASSUME_ROLE_WITH_SAML_RESPONSE: &str = r#"<AssumeRoleWithSAMLResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleWithSAMLResult>
+    <Credentials>
+      <AccessKeyId>ASIAOKTA</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>session-token</SessionToken>
+      <Expiration>2030-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleWithSAMLResult>
+</AssumeRoleWithSAMLResponse>"#;
+
+    // Okta escapes the characters base64 shares with HTML
+    fn sso_page() -> String {
+        let value = encoded_saml_response()
+            .replace('+', "&#x2b;")
+            .replace('=', "&#x3d;");
+        format!(
+            r#"<html><body><form method="POST"><input name="SAMLResponse" type="hidden" value="{}"/></form></body></html>"#,
+            value
+        )
+    }
+
+    // Okta's OAuth and SSO endpoints and STS, behind one loopback port; device
+    // token polls fail with the given errors before they succeed
+    pub async fn start(device_token_errors: &'static [&'static str]) -> StandIn {
+        StandIn::start(move |request, earlier| {
+            let is_device_poll = |request: &Received| {
+                request.form("grant_type").as_deref() == Some(DEVICE_CODE_GRANT_TYPE)
+            };
+
+            match request.path.split('?').next().unwrap_or_default() {
+                "/oauth2/v1/device/authorize" => Reply::json(
+                    "200 OK",
+                    json!({
+                        "device_code": "device-code",
+                        "user_code": "WXYZ",
+                        "verification_uri": "https://example.okta.com/activate",
+                        "expires_in": 600,
+                        "interval": 1,
+                    }),
+                ),
+                "/oauth2/v1/token" if is_device_poll(request) => {
+                    let polls = earlier.iter().filter(|r| is_device_poll(r)).count();
+                    match device_token_errors.get(polls) {
+                        Some(error) => Reply::json("400 Bad Request", json!({ "error": error })),
+                        None => Reply::json(
+                            "200 OK",
+                            json!({ "access_token": "access-token", "id_token": "id-token" }),
+                        ),
+                    }
+                }
+                "/oauth2/v1/token" => {
+                    Reply::json("200 OK", json!({ "access_token": "web-sso-token" }))
+                }
+                "/login/token/sso" => Reply::text("200 OK", crate::loopback::HTML, sso_page()),
+                "/" => Reply::text(
+                    "200 OK",
+                    "text/xml",
+                    ASSUME_ROLE_WITH_SAML_RESPONSE.to_string(),
+                ),
+                _ => Reply::json("404 Not Found", json!({})),
+            }
+        })
+        .await
+    }
+
+    pub fn settings(org_domain: &str) -> OktaSettings {
+        OktaSettings {
+            org_domain: org_domain.to_string(),
+            oidc_client_id: Some("client-id".to_string()),
+            federation_app_id: Some("federation-app".to_string()),
+            iam_role: Some(OPS_ROLE_ARN.to_string()),
+            iam_idp: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stand_in::{self, encoded_saml_response, OPS_ROLE_ARN, PROVIDER_ARN};
+    use super::*;
+    use crate::test_support::TestEnv;
+
+    #[tokio::test]
+    async fn login_exchanges_the_device_tokens_for_a_saml_session() {
+        let mut test_env = TestEnv::new("okta-login").await;
+        let okta = stand_in::start(&["authorization_pending"]).await;
+        test_env.set("AWS_ENDPOINT_URL_STS", &okta.url);
+
+        let credentials = login(&stand_in::settings(&okta.url), Some("eu-west-1"))
+            .await
+            .unwrap();
+
+        assert_eq!(credentials.access_key_id(), "ASIAOKTA");
+        assert_eq!(credentials.session_token(), Some("session-token"));
+        assert!(credentials.expiry().is_some());
+
+        let authorize = okta.received_at("/oauth2/v1/device/authorize");
+        assert_eq!(authorize[0].form("client_id").as_deref(), Some("client-id"));
+        assert_eq!(authorize[0].form("scope").as_deref(), Some(SCOPES));
+
+        // One pending poll, the approved one and the token exchange
+        let token_requests = okta.received_at("/oauth2/v1/token");
+        assert_eq!(token_requests.len(), 3);
+        let exchange = &token_requests[2];
+        assert_eq!(
+            exchange.form("grant_type").as_deref(),
+            Some(TOKEN_EXCHANGE_GRANT_TYPE)
+        );
+        assert_eq!(
+            exchange.form("actor_token").as_deref(),
+            Some("access-token")
+        );
+        assert_eq!(exchange.form("subject_token").as_deref(), Some("id-token"));
+        assert_eq!(
+            exchange.form("requested_token_type").as_deref(),
+            Some(WEB_SSO_TOKEN_TYPE)
+        );
+        assert_eq!(
+            exchange.form("audience").as_deref(),
+            Some("urn:okta:apps:federation-app")
+        );
+
+        let sso = okta.received_at("/login/token/sso");
+        assert_eq!(sso.len(), 1);
+        assert!(sso[0].path.ends_with("?token=web-sso-token"));
+
+        let sts = okta.received_at("/");
+        assert_eq!(sts.len(), 1);
+        assert_eq!(sts[0].form("Action").as_deref(), Some("AssumeRoleWithSAML"));
+        assert_eq!(sts[0].form("RoleArn").as_deref(), Some(OPS_ROLE_ARN));
+        assert_eq!(sts[0].form("PrincipalArn").as_deref(), Some(PROVIDER_ARN));
+        assert_eq!(sts[0].form("DurationSeconds").as_deref(), Some("3600"));
+        assert_eq!(sts[0].form("SAMLAssertion"), Some(encoded_saml_response()));
+    }
+
+    #[tokio::test]
+    async fn login_fails_when_the_device_is_not_approved() {
+        let _test_env = TestEnv::new("okta-denied").await;
+        let okta = stand_in::start(&["access_denied"]).await;
+
+        let error = login(&stand_in::settings(&okta.url), None)
+            .await
+            .unwrap_err();
+
+        assert!(error.to_string().contains("access_denied"));
+        assert!(okta.received_at("/login/token/sso").is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use std::io::Write;
+
+// Sends everything printed to stdout to stderr until dropped, so the login
+// flows can run when stdout belongs to a script or pipe
+pub struct StdoutToStderr {
+    #[cfg(unix)]
+    saved_stdout: libc::c_int,
+    #[cfg(windows)]
+    saved_stdout: windows_sys::Win32::Foundation::HANDLE,
+}
+
+#[cfg(unix)]
+impl StdoutToStderr {
+    pub fn new() -> Result<Self> {
+        std::io::stdout().flush()?;
+
+        // SAFETY: only duplicates the process's own standard descriptors
+        let saved_stdout = unsafe { libc::dup(libc::STDOUT_FILENO) };
+        if saved_stdout < 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if unsafe { libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) } < 0 {
+            let error = std::io::Error::last_os_error();
+            unsafe { libc::close(saved_stdout) };
+            return Err(error.into());
+        }
+
+        Ok(StdoutToStderr { saved_stdout })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        let _ = std::io::stdout().flush();
+
+        // SAFETY: restores the descriptor saved in new()
+        unsafe {
+            libc::dup2(self.saved_stdout, libc::STDOUT_FILENO);
+            libc::close(self.saved_stdout);
+        }
+    }
+}
+
+// Rust looks up the standard handles on every write on Windows, so swapping
+// the process's stdout handle redirects println! and spawned commands alike
+#[cfg(windows)]
+impl StdoutToStderr {
+    pub fn new() -> Result<Self> {
+        use windows_sys::Win32::Foundation::INVALID_HANDLE_VALUE;
+        use windows_sys::Win32::System::Console::{
+            GetStdHandle, SetStdHandle, STD_ERROR_HANDLE, STD_OUTPUT_HANDLE,
+        };
+
+        std::io::stdout().flush()?;
+
+        // SAFETY: only reads and replaces the process's own standard handles
+        let saved_stdout = unsafe { GetStdHandle(STD_OUTPUT_HANDLE) };
+        let stderr = unsafe { GetStdHandle(STD_ERROR_HANDLE) };
+        if saved_stdout == INVALID_HANDLE_VALUE || stderr == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        if unsafe { SetStdHandle(STD_OUTPUT_HANDLE, stderr) } == 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+
+        Ok(StdoutToStderr { saved_stdout })
+    }
+}
+
+#[cfg(windows)]
+impl Drop for StdoutToStderr {
+    fn drop(&mut self) {
+        use windows_sys::Win32::System::Console::{SetStdHandle, STD_OUTPUT_HANDLE};
+
+        let _ = std::io::stdout().flush();
+
+        // SAFETY: restores the handle saved in new()
+        unsafe {
+            SetStdHandle(STD_OUTPUT_HANDLE, self.saved_stdout);
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::HashMap;
+
+// A profile from ~/.aws/config, ~/.aws/credentials or both
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub kind: ProfileKind,
+    pub region: Option<String>,
+    // Settings aaa has no use for (output, cli_pager, endpoint_url, s3, ...) in
+    // file order, written back as they were
+    pub extra: Vec<(String, String)>,
+    pub source: ProfileSource,
+}
+
+// Which of the two AWS files have a section for the profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileSource {
+    Config,
+    Credentials,
+    Both,
+}
+
+impl ProfileSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProfileSource::Config => "config",
+            ProfileSource::Credentials => "credentials",
+            ProfileSource::Both => "config + credentials",
+        }
+    }
+}
+
+// How a profile gets its credentials, with the settings each way needs
+#[derive(Debug, Clone)]
+pub enum ProfileKind {
+    // Keys from ~/.aws/credentials, traded for an MFA session when mfa_serial
+    // is set
+    Static { mfa_serial: Option<String> },
+    // External command that provides the credentials
+    Process { command: String },
+    Sso(SsoSettings),
+    Okta(OktaSettings),
+    Saml(SamlSettings),
+    WebIdentity(WebIdentitySettings),
+    RolesAnywhere(RolesAnywhereSettings),
+    AssumeRole(AssumeRoleSettings),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SsoSettings {
+    // [sso-session] section the start URL, region and scopes come from
+    pub session: Option<String>,
+    pub start_url: Option<String>,
+    pub region: Option<String>,
+    pub registration_scopes: Option<String>,
+    pub account_id: Option<String>,
+    pub role_name: Option<String>,
+}
+
+// An [sso-session] section of ~/.aws/config, shared by the SSO profiles that
+// name it
+#[derive(Debug, Clone)]
+pub struct SsoSession {
+    pub name: String,
+    pub sso_start_url: Option<String>,
+    pub sso_region: Option<String>,
+    pub sso_registration_scopes: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct OktaSettings {
+    pub org_domain: String,
+    pub oidc_client_id: Option<String>,
+    pub federation_app_id: Option<String>,
+    pub iam_role: Option<String>,
+    pub iam_idp: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SamlSettings {
+    pub idp_url: Option<String>,
+    pub provider_arn: Option<String>,
+    pub role_arn: Option<String>,
+}
+
+// OIDC token exchanged through AssumeRoleWithWebIdentity, read from a file or
+// requested from the CI provider
+#[derive(Debug, Clone)]
+pub struct WebIdentitySettings {
+    pub role_arn: String,
+    pub token_file: Option<String>,
+    pub provider: Option<String>,
+    pub audience: Option<String>,
+    pub token_env: Option<String>,
+    pub role_session_name: Option<String>,
+    pub duration_seconds: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RolesAnywhereSettings {
+    pub certificate: Option<String>,
+    pub certificate_chain: Option<String>,
+    pub private_key: Option<String>,
+    pub trust_anchor_arn: String,
+    pub profile_arn: Option<String>,
+    pub role_arn: Option<String>,
+    pub role_session_name: Option<String>,
+    pub duration_seconds: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AssumeRoleSettings {
+    pub role_arn: String,
+    pub source_profile: Option<String>,
+    pub credential_source: Option<String>,
+    pub external_id: Option<String>,
+    pub role_session_name: Option<String>,
+    pub duration_seconds: Option<String>,
+    // MFA device used for AssumeRole
+    pub mfa_serial: Option<String>,
+}
+
+impl ProfileKind {
+    // Shown next to the profile name in the menu
+    pub fn label(&self) -> &'static str {
+        match self {
+            ProfileKind::Static { mfa_serial: None } => "Standard",
+            ProfileKind::Static {
+                mfa_serial: Some(_),
+            } => "Standard+MFA",
+            ProfileKind::Process { .. } => "Process",
+            ProfileKind::Sso(_) => "SSO",
+            ProfileKind::Okta(_) => "Okta",
+            ProfileKind::Saml(_) => "SAML",
+            ProfileKind::WebIdentity(_) => "WebIdentity",
+            ProfileKind::RolesAnywhere(_) => "RolesAnywhere",
+            ProfileKind::AssumeRole(_) => "AssumeRole",
+        }
+    }
+}
+
+// Keys of a config section, taken out as the profile kind claims them; what is
+// left becomes the profile's extra settings
+struct SectionEntries(Vec<(String, String)>);
+
+impl SectionEntries {
+    fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+    }
+
+    fn has(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    // Later duplicates win, like in the AWS CLI
+    fn take(&mut self, key: &str) -> Option<String> {
+        let value = self.get(key);
+        self.0.retain(|(k, _)| k != key);
+        value
+    }
+}
+
+impl Profile {
+    // Builds a profile from the settings of its section in either file; the
+    // kind is decided by the keys present, checked in this order: Okta, SAML,
+    // Roles Anywhere, web identity, assume-role, SSO, credential_process, MFA.
+    // credential_process comes before mfa_serial as it does in botocore, so an
+    // mfa_serial next to it stays an extra setting
+    pub fn from_section(
+        name: String,
+        entries: Vec<(String, String)>,
+        sso_sessions: &HashMap<String, SsoSession>,
+        source: ProfileSource,
+    ) -> Profile {
+        let mut entries = SectionEntries(entries);
+        let region = entries.take("region");
+
+        let kind = if let Some(org_domain) = entries.take("okta_org_domain") {
+            ProfileKind::Okta(OktaSettings {
+                org_domain,
+                oidc_client_id: entries.take("okta_oidc_client_id"),
+                federation_app_id: entries.take("okta_aws_account_federation_app_id"),
+                iam_role: entries.take("okta_aws_iam_role"),
+                iam_idp: entries.take("okta_aws_iam_idp"),
+            })
+        } else if entries.has("saml_idp_url") || entries.has("saml_provider_arn") {
+            ProfileKind::Saml(SamlSettings {
+                idp_url: entries.take("saml_idp_url"),
+                provider_arn: entries.take("saml_provider_arn"),
+                role_arn: entries.take("saml_role_arn"),
+            })
+        } else if let Some(trust_anchor_arn) = entries.take("rolesanywhere_trust_anchor_arn") {
+            ProfileKind::RolesAnywhere(RolesAnywhereSettings {
+                certificate: entries.take("rolesanywhere_certificate"),
+                certificate_chain: entries.take("rolesanywhere_certificate_chain"),
+                private_key: entries.take("rolesanywhere_private_key"),
+                trust_anchor_arn,
+                profile_arn: entries.take("rolesanywhere_profile_arn"),
+                role_arn: entries.take("role_arn"),
+                role_session_name: entries.take("role_session_name"),
+                duration_seconds: entries.take("duration_seconds"),
+            })
+        } else if entries.has("role_arn")
+            && (entries.has("web_identity_token_file") || entries.has("web_identity_provider"))
+        {
+            ProfileKind::WebIdentity(WebIdentitySettings {
+                role_arn: entries.take("role_arn").unwrap_or_default(),
+                token_file: entries.take("web_identity_token_file"),
+                provider: entries.take("web_identity_provider"),
+                audience: entries.take("web_identity_audience"),
+                token_env: entries.take("web_identity_token_env"),
+                role_session_name: entries.take("role_session_name"),
+                duration_seconds: entries.take("duration_seconds"),
+            })
+        } else if entries.has("role_arn")
+            && (entries.has("source_profile") || entries.has("credential_source"))
+        {
+            ProfileKind::AssumeRole(AssumeRoleSettings {
+                role_arn: entries.take("role_arn").unwrap_or_default(),
+                source_profile: entries.take("source_profile"),
+                credential_source: entries.take("credential_source"),
+                external_id: entries.take("external_id"),
+                role_session_name: entries.take("role_session_name"),
+                duration_seconds: entries.take("duration_seconds"),
+                mfa_serial: entries.take("mfa_serial"),
+            })
+        } else if entries.has("sso_start_url") || entries.has("sso_session") {
+            // The start URL and region of an [sso-session] win over any left in
+            // the profile, which then stay untouched as extra settings
+            let session_name = entries.take("sso_session");
+            let session = session_name
+                .as_ref()
+                .and_then(|name| sso_sessions.get(name));
+            let (start_url, region) = if session_name.is_some() {
+                (
+                    session
+                        .and_then(|s| s.sso_start_url.clone())
+                        .or_else(|| entries.get("sso_start_url")),
+                    session
+                        .and_then(|s| s.sso_region.clone())
+                        .or_else(|| entries.get("sso_region")),
+                )
+            } else {
+                (entries.take("sso_start_url"), entries.take("sso_region"))
+            };
+
+            ProfileKind::Sso(SsoSettings {
+                registration_scopes: session.and_then(|s| s.sso_registration_scopes.clone()),
+                session: session_name,
+                start_url,
+                region,
+                account_id: entries.take("sso_account_id"),
+                role_name: entries.take("sso_role_name"),
+            })
+        } else if let Some(command) = entries.take("credential_process") {
+            ProfileKind::Process { command }
+        } else if let Some(mfa_serial) = entries.take("mfa_serial") {
+            ProfileKind::Static {
+                mfa_serial: Some(mfa_serial),
+            }
+        } else {
+            ProfileKind::Static { mfa_serial: None }
+        };
+
+        Profile {
+            name,
+            kind,
+            region,
+            extra: entries.0,
+            source,
+        }
+    }
+
+    // Config keys and values of the profile: its kind's settings, the region
+    // and then the extra settings
+    pub fn entries(&self) -> Vec<(String, String)> {
+        let mut entries: Vec<(&str, Option<&String>)> = Vec::new();
+
+        match &self.kind {
+            ProfileKind::Static { mfa_serial } => {
+                entries.push(("mfa_serial", mfa_serial.as_ref()));
+            }
+            ProfileKind::Process { command } => {
+                entries.push(("credential_process", Some(command)));
+            }
+            ProfileKind::Sso(sso) => {
+                // Start URL and region live in the [sso-session] section when
+                // the profile references one
+                if sso.session.is_some() {
+                    entries.push(("sso_session", sso.session.as_ref()));
+                } else {
+                    entries.push(("sso_start_url", sso.start_url.as_ref()));
+                    entries.push(("sso_region", sso.region.as_ref()));
+                }
+                entries.push(("sso_account_id", sso.account_id.as_ref()));
+                entries.push(("sso_role_name", sso.role_name.as_ref()));
+            }
+            ProfileKind::Okta(okta) => {
+                entries.push(("okta_org_domain", Some(&okta.org_domain)));
+                entries.push(("okta_oidc_client_id", okta.oidc_client_id.as_ref()));
+                entries.push((
+                    "okta_aws_account_federation_app_id",
+                    okta.federation_app_id.as_ref(),
+                ));
+                entries.push(("okta_aws_iam_role", okta.iam_role.as_ref()));
+                entries.push(("okta_aws_iam_idp", okta.iam_idp.as_ref()));
+            }
+            ProfileKind::Saml(saml) => {
+                entries.push(("saml_idp_url", saml.idp_url.as_ref()));
+                entries.push(("saml_provider_arn", saml.provider_arn.as_ref()));
+                entries.push(("saml_role_arn", saml.role_arn.as_ref()));
+            }
+            ProfileKind::RolesAnywhere(roles_anywhere) => {
+                entries.push((
+                    "rolesanywhere_certificate",
+                    roles_anywhere.certificate.as_ref(),
+                ));
+                entries.push((
+                    "rolesanywhere_certificate_chain",
+                    roles_anywhere.certificate_chain.as_ref(),
+                ));
+                entries.push((
+                    "rolesanywhere_private_key",
+                    roles_anywhere.private_key.as_ref(),
+                ));
+                entries.push((
+                    "rolesanywhere_trust_anchor_arn",
+                    Some(&roles_anywhere.trust_anchor_arn),
+                ));
+                entries.push((
+                    "rolesanywhere_profile_arn",
+                    roles_anywhere.profile_arn.as_ref(),
+                ));
+                entries.push(("role_arn", roles_anywhere.role_arn.as_ref()));
+                entries.push((
+                    "role_session_name",
+                    roles_anywhere.role_session_name.as_ref(),
+                ));
+                entries.push(("duration_seconds", roles_anywhere.duration_seconds.as_ref()));
+            }
+            ProfileKind::AssumeRole(assume_role) => {
+                entries.push(("role_arn", Some(&assume_role.role_arn)));
+                entries.push(("source_profile", assume_role.source_profile.as_ref()));
+                entries.push(("credential_source", assume_role.credential_source.as_ref()));
+                entries.push(("external_id", assume_role.external_id.as_ref()));
+                entries.push(("role_session_name", assume_role.role_session_name.as_ref()));
+                entries.push(("duration_seconds", assume_role.duration_seconds.as_ref()));
+                entries.push(("mfa_serial", assume_role.mfa_serial.as_ref()));
+            }
+            ProfileKind::WebIdentity(web_identity) => {
+                entries.push(("role_arn", Some(&web_identity.role_arn)));
+                entries.push(("role_session_name", web_identity.role_session_name.as_ref()));
+                entries.push(("duration_seconds", web_identity.duration_seconds.as_ref()));
+                entries.push(("web_identity_token_file", web_identity.token_file.as_ref()));
+                entries.push(("web_identity_provider", web_identity.provider.as_ref()));
+                entries.push(("web_identity_audience", web_identity.audience.as_ref()));
+                entries.push(("web_identity_token_env", web_identity.token_env.as_ref()));
+            }
+        }
+
+        entries.push(("region", self.region.as_ref()));
+
+        entries
+            .into_iter()
+            .filter_map(|(key, value)| value.map(|value| (key.to_string(), value.clone())))
+            .chain(self.extra.iter().cloned())
+            .collect()
+    }
+
+    // Profile an assume-role profile takes its base credentials from
+    pub fn source_profile(&self) -> Option<&str> {
+        match &self.kind {
+            ProfileKind::AssumeRole(assume_role) => assume_role.source_profile.as_deref(),
+            _ => None,
+        }
+    }
+
+    // Why the profile cannot work as its two files stand, e.g. static keys that
+    // were never written to the credentials file
+    pub fn mismatch(&self) -> Option<&'static str> {
+        let needs_keys = matches!(self.kind, ProfileKind::Static { .. })
+            || self.source_profile() == Some(self.name.as_str());
+
+        if needs_keys && self.source == ProfileSource::Config {
+            Some("missing from the credentials file")
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(entries: &[(&str, &str)], source: ProfileSource) -> Profile {
+        let sso_sessions = HashMap::from([(
+            "corp".to_string(),
+            SsoSession {
+                name: "corp".to_string(),
+                sso_start_url: Some("https://corp.awsapps.com/start".to_string()),
+                sso_region: Some("eu-west-1".to_string()),
+                sso_registration_scopes: Some("sso:account:access".to_string()),
+            },
+        )]);
+        let entries = entries
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Profile::from_section("test".to_string(), entries, &sso_sessions, source)
+    }
+
+    // One section per kind, keys in the order entries() writes them
+    const SECTIONS: [(&str, &[(&str, &str)]); 10] = [
+        ("Standard", &[("region", "eu-west-1")]),
+        ("Standard+MFA", &[("mfa_serial", "arn:aws:iam::123:mfa/me")]),
+        (
+            "Process",
+            &[
+                ("credential_process", "vault-creds dev"),
+                ("mfa_serial", "arn:aws:iam::123:mfa/me"),
+            ],
+        ),
+        (
+            "SSO",
+            &[
+                ("sso_start_url", "https://dev.awsapps.com/start"),
+                ("sso_region", "us-east-1"),
+                ("sso_account_id", "123"),
+                ("sso_role_name", "Dev"),
+            ],
+        ),
+        (
+            "SSO",
+            &[
+                ("sso_session", "corp"),
+                ("sso_account_id", "123"),
+                ("sso_role_name", "Dev"),
+                ("region", "eu-west-1"),
+            ],
+        ),
+        (
+            "Okta",
+            &[
+                ("okta_org_domain", "corp.okta.com"),
+                ("okta_oidc_client_id", "client"),
+                ("okta_aws_account_federation_app_id", "app"),
+                ("okta_aws_iam_role", "arn:aws:iam::123:role/Dev"),
+                ("okta_aws_iam_idp", "arn:aws:iam::123:saml-provider/Okta"),
+            ],
+        ),
+        (
+            "SAML",
+            &[
+                ("saml_idp_url", "https://idp.example.com/sso"),
+                ("saml_provider_arn", "arn:aws:iam::123:saml-provider/Idp"),
+                ("saml_role_arn", "arn:aws:iam::123:role/Dev"),
+            ],
+        ),
+        (
+            "RolesAnywhere",
+            &[
+                ("rolesanywhere_certificate", "cert.pem"),
+                ("rolesanywhere_private_key", "key.pem"),
+                (
+                    "rolesanywhere_trust_anchor_arn",
+                    "arn:aws:rolesanywhere:anchor",
+                ),
+                ("rolesanywhere_profile_arn", "arn:aws:rolesanywhere:profile"),
+                ("role_arn", "arn:aws:iam::123:role/Dev"),
+            ],
+        ),
+        (
+            "WebIdentity",
+            &[
+                ("role_arn", "arn:aws:iam::123:role/Ci"),
+                ("web_identity_token_file", "/run/token"),
+                ("region", "eu-west-1"),
+                ("output", "json"),
+            ],
+        ),
+        (
+            "AssumeRole",
+            &[
+                ("role_arn", "arn:aws:iam::123:role/Admin"),
+                ("source_profile", "base"),
+                ("external_id", "shared"),
+                ("mfa_serial", "arn:aws:iam::123:mfa/me"),
+            ],
+        ),
+    ];
+
+    #[test]
+    fn kind_follows_the_keys_present() {
+        for (label, entries) in SECTIONS {
+            assert_eq!(
+                profile(entries, ProfileSource::Both).kind.label(),
+                label,
+                "{:?}",
+                entries
+            );
+        }
+    }
+
+    #[test]
+    fn credential_process_wins_over_mfa_serial() {
+        let profile = profile(SECTIONS[2].1, ProfileSource::Config);
+
+        assert!(
+            matches!(&profile.kind, ProfileKind::Process { command } if command == "vault-creds dev")
+        );
+        assert_eq!(
+            profile.extra,
+            [(
+                "mfa_serial".to_string(),
+                "arn:aws:iam::123:mfa/me".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn sso_session_settings_come_from_the_session_section() {
+        let profile = profile(
+            &[
+                ("sso_session", "corp"),
+                ("sso_start_url", "https://stale.awsapps.com/start"),
+            ],
+            ProfileSource::Config,
+        );
+
+        let ProfileKind::Sso(sso) = &profile.kind else {
+            panic!("not an SSO profile: {:?}", profile.kind);
+        };
+        assert_eq!(
+            sso.start_url.as_deref(),
+            Some("https://corp.awsapps.com/start")
+        );
+        assert_eq!(sso.region.as_deref(), Some("eu-west-1"));
+        assert_eq!(
+            sso.registration_scopes.as_deref(),
+            Some("sso:account:access")
+        );
+        // The profile's own start URL is left as it was written
+        assert_eq!(
+            profile.extra,
+            [(
+                "sso_start_url".to_string(),
+                "https://stale.awsapps.com/start".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn entries_round_trip_through_from_section() {
+        for (_, entries) in SECTIONS {
+            let expected: Vec<(String, String)> = entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+
+            assert_eq!(profile(entries, ProfileSource::Both).entries(), expected);
+        }
+    }
+
+    #[test]
+    fn mismatch_flags_keys_missing_from_the_credentials_file() {
+        let base_of_itself = [
+            ("role_arn", "arn:aws:iam::123:role/Admin"),
+            ("source_profile", "test"),
+        ];
+
+        assert!(profile(SECTIONS[0].1, ProfileSource::Config)
+            .mismatch()
+            .is_some());
+        assert!(profile(SECTIONS[1].1, ProfileSource::Config)
+            .mismatch()
+            .is_some());
+        assert!(profile(&base_of_itself, ProfileSource::Config)
+            .mismatch()
+            .is_some());
+
+        assert!(profile(SECTIONS[0].1, ProfileSource::Both)
+            .mismatch()
+            .is_none());
+        assert!(profile(SECTIONS[0].1, ProfileSource::Credentials)
+            .mismatch()
+            .is_none());
+        assert!(profile(&base_of_itself, ProfileSource::Both)
+            .mismatch()
+            .is_none());
+        // Only static keys live in the credentials file
+        for (_, entries) in &SECTIONS[2..] {
+            assert!(profile(entries, ProfileSource::Config).mismatch().is_none());
+        }
+    }
+}
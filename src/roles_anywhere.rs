@@ -0,0 +1,658 @@
+use crate::profile::RolesAnywhereSettings;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use aws_lc_rs::digest::{digest, SHA256};
+use aws_lc_rs::rand::SystemRandom;
+use aws_lc_rs::signature::{
+    EcdsaKeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING, RSA_PKCS1_SHA256,
+};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::time::SystemTime;
+use x509_parser::pem::Pem;
+use x509_parser::prelude::*;
+
+const SERVICE: &str = "rolesanywhere";
+const ENDPOINT_URL_ENV: &str = "AWS_ENDPOINT_URL_ROLESANYWHERE";
+
+// End-entity certificate sent in the X-Amz-X509 header
+pub struct Certificate {
+    pub der: Vec<u8>,
+    // Decimal serial number, used as the access key in the credential scope
+    pub serial_number: String,
+    pub subject: String,
+    pub not_after: DateTime<Utc>,
+}
+
+enum SigningKey {
+    Rsa(RsaKeyPair),
+    Ecdsa(EcdsaKeyPair),
+}
+
+impl SigningKey {
+    fn algorithm(&self) -> &'static str {
+        match self {
+            SigningKey::Rsa(_) => "AWS4-X509-RSA-SHA256",
+            SigningKey::Ecdsa(_) => "AWS4-X509-ECDSA-SHA256",
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+        match self {
+            SigningKey::Rsa(key) => {
+                let mut signature = vec![0u8; key.public_modulus_len()];
+                key.sign(&RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                    .map_err(|_| anyhow!("Failed to sign the request with the RSA key"))?;
+                Ok(signature)
+            }
+            SigningKey::Ecdsa(key) => {
+                let signature = key
+                    .sign(&rng, message)
+                    .map_err(|_| anyhow!("Failed to sign the request with the EC key"))?;
+                Ok(signature.as_ref().to_vec())
+            }
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionRequest<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_seconds: Option<i32>,
+    profile_arn: &'a str,
+    role_arn: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role_session_name: Option<&'a str>,
+    trust_anchor_arn: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateSessionResponse {
+    credential_set: Vec<CredentialSet>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CredentialSet {
+    credentials: SessionCredentials,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: String,
+}
+
+fn read_pem_blocks(path: &str) -> Result<Vec<Pem>> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read '{}'", path))?;
+
+    let blocks = Pem::iter_from_buffer(&contents)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("'{}' is not a valid PEM file", path))?;
+
+    if blocks.is_empty() {
+        return Err(anyhow!("No PEM blocks found in '{}'", path));
+    }
+
+    Ok(blocks)
+}
+
+pub fn load_certificate(path: &str) -> Result<Certificate> {
+    let blocks = read_pem_blocks(path)?;
+    let block = blocks
+        .into_iter()
+        .find(|block| block.label == "CERTIFICATE")
+        .ok_or_else(|| anyhow!("No certificate found in '{}'", path))?;
+
+    let (_, certificate) = X509Certificate::from_der(&block.contents)
+        .with_context(|| format!("Failed to parse the certificate in '{}'", path))?;
+
+    let serial_number = certificate.serial.to_string();
+    let subject = certificate.subject().to_string();
+    let not_after = DateTime::from_timestamp(certificate.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| anyhow!("Certificate in '{}' has an invalid expiry", path))?;
+
+    Ok(Certificate {
+        der: block.contents,
+        serial_number,
+        subject,
+        not_after,
+    })
+}
+
+fn load_certificate_chain(path: &str) -> Result<Vec<Vec<u8>>> {
+    Ok(read_pem_blocks(path)?
+        .into_iter()
+        .filter(|block| block.label == "CERTIFICATE")
+        .map(|block| block.contents)
+        .collect())
+}
+
+// Accepts PKCS#8, PKCS#1 (RSA) and SEC1 (EC) keys; only RSA and P-256 can sign with SHA-256
+fn load_private_key(path: &str) -> Result<SigningKey> {
+    let blocks = read_pem_blocks(path)?;
+
+    for block in blocks {
+        let key = match block.label.as_str() {
+            "PRIVATE KEY" => RsaKeyPair::from_pkcs8(&block.contents)
+                .map(SigningKey::Rsa)
+                .or_else(|_| {
+                    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &block.contents)
+                        .map(SigningKey::Ecdsa)
+                }),
+            "RSA PRIVATE KEY" => RsaKeyPair::from_der(&block.contents).map(SigningKey::Rsa),
+            "EC PRIVATE KEY" => {
+                EcdsaKeyPair::from_private_key_der(&ECDSA_P256_SHA256_ASN1_SIGNING, &block.contents)
+                    .map(SigningKey::Ecdsa)
+            }
+            "ENCRYPTED PRIVATE KEY" => {
+                return Err(anyhow!(
+                    "Encrypted private keys are not supported, decrypt '{}' first",
+                    path
+                ))
+            }
+            _ => continue,
+        };
+
+        return key.map_err(|e| {
+            anyhow!(
+                "Unsupported private key in '{}' (only RSA and EC P-256 keys are supported): {}",
+                path,
+                e
+            )
+        });
+    }
+
+    Err(anyhow!("No private key found in '{}'", path))
+}
+
+// The trust anchor ARN determines the region CreateSession is called in
+fn region_from_arn(arn: &str) -> Result<&str> {
+    arn.split(':')
+        .nth(3)
+        .filter(|region| !region.is_empty())
+        .ok_or_else(|| anyhow!("'{}' is not a valid trust anchor ARN", arn))
+}
+
+fn endpoint_url(region: &str) -> String {
+    match std::env::var(ENDPOINT_URL_ENV) {
+        Ok(url) if !url.is_empty() => url.trim_end_matches('/').to_string(),
+        _ => format!("https://{}.{}.amazonaws.com", SERVICE, region),
+    }
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    hex::encode(digest(&SHA256, data))
+}
+
+pub async fn create_session(roles_anywhere: &RolesAnywhereSettings) -> Result<Credentials> {
+    let certificate_path = roles_anywhere
+        .certificate
+        .as_deref()
+        .ok_or_else(|| anyhow!("Certificate is required but not configured"))?;
+    let private_key_path = roles_anywhere
+        .private_key
+        .as_deref()
+        .ok_or_else(|| anyhow!("Private key is required but not configured"))?;
+    let trust_anchor_arn = roles_anywhere.trust_anchor_arn.as_str();
+    let profile_arn = roles_anywhere
+        .profile_arn
+        .as_deref()
+        .ok_or_else(|| anyhow!("Roles Anywhere profile ARN is required but not configured"))?;
+    let role_arn = roles_anywhere
+        .role_arn
+        .as_deref()
+        .ok_or_else(|| anyhow!("Role ARN is required but not configured"))?;
+    let duration_seconds = roles_anywhere
+        .duration_seconds
+        .as_deref()
+        .map(str::parse::<i32>)
+        .transpose()
+        .context("duration_seconds must be a number of seconds")?;
+
+    let certificate = load_certificate(certificate_path)?;
+    let key = load_private_key(private_key_path)?;
+    let chain = match &roles_anywhere.certificate_chain {
+        Some(chain_path) => load_certificate_chain(chain_path)?,
+        None => Vec::new(),
+    };
+
+    let region = region_from_arn(trust_anchor_arn)?;
+    let url = url::Url::parse(&format!("{}/sessions", endpoint_url(region)))
+        .context("Invalid IAM Roles Anywhere endpoint")?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let body = serde_json::to_vec(&CreateSessionRequest {
+        duration_seconds,
+        profile_arn,
+        role_arn,
+        role_session_name: roles_anywhere.role_session_name.as_deref(),
+        trust_anchor_arn,
+    })?;
+
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let scope = format!(
+        "{}/{}/{}/aws4_request",
+        now.format("%Y%m%d"),
+        region,
+        SERVICE
+    );
+
+    let engine = base64::engine::general_purpose::STANDARD;
+    let x509 = engine.encode(&certificate.der);
+    let x509_chain = chain
+        .iter()
+        .map(|der| engine.encode(der))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    // Header names are lowercase and already in sorted order
+    let mut headers = vec![
+        ("content-type", "application/json".to_string()),
+        ("host", host),
+        ("x-amz-date", amz_date.clone()),
+        ("x-amz-x509", x509),
+    ];
+    if !x509_chain.is_empty() {
+        headers.push(("x-amz-x509-chain", x509_chain));
+    }
+
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "POST\n{}\n\n{}\n{}\n{}",
+        url.path(),
+        canonical_headers,
+        signed_headers,
+        hex_sha256(&body)
+    );
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        key.algorithm(),
+        amz_date,
+        scope,
+        hex_sha256(canonical_request.as_bytes())
+    );
+    let signature = hex::encode(key.sign(string_to_sign.as_bytes())?);
+
+    let authorization = format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        key.algorithm(),
+        certificate.serial_number,
+        scope,
+        signed_headers,
+        signature
+    );
+
+    let mut request = reqwest::Client::new()
+        .post(url)
+        .header("Authorization", authorization)
+        .body(body);
+    for (name, value) in &headers {
+        if *name != "host" {
+            request = request.header(*name, value);
+        }
+    }
+
+    let response = request
+        .send()
+        .await
+        .context("Failed to reach the IAM Roles Anywhere endpoint")?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(anyhow!(
+            "IAM Roles Anywhere CreateSession failed with HTTP {}: {}",
+            status,
+            message
+        ));
+    }
+
+    let session: CreateSessionResponse = response
+        .json()
+        .await
+        .context("Failed to parse IAM Roles Anywhere response")?;
+
+    let credentials = session
+        .credential_set
+        .into_iter()
+        .next()
+        .map(|set| set.credentials)
+        .ok_or_else(|| anyhow!("IAM Roles Anywhere returned no credentials"))?;
+
+    let expiration = DateTime::parse_from_rfc3339(&credentials.expiration)
+        .context("IAM Roles Anywhere returned an invalid credential expiration")?;
+
+    Ok(Credentials::new(
+        credentials.access_key_id,
+        credentials.secret_access_key,
+        Some(credentials.session_token),
+        Some(SystemTime::from(expiration)),
+        "aaa-roles-anywhere",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Received, Reply, StandIn, TestEnv};
+    use aws_lc_rs::encoding::AsDer;
+    use aws_lc_rs::rsa::KeySize;
+    use aws_lc_rs::signature::{
+        KeyPair, UnparsedPublicKey, VerificationAlgorithm, ECDSA_P256_SHA256_ASN1,
+        RSA_PKCS1_2048_8192_SHA256,
+    };
+    use serde_json::json;
+    use std::path::Path;
+
+    const TRUST_ANCHOR_ARN: &str =
+        "arn:aws:rolesanywhere:eu-west-1:111111111111:trust-anchor/11111111-2222-3333-4444-555555555555";
+    const PROFILE_ARN: &str =
+        "arn:aws:rolesanywhere:eu-west-1:111111111111:profile/66666666-7777-8888-9999-000000000000";
+    const ROLE_ARN: &str = "arn:aws:iam::111111111111:role/Workload";
+
+    // DER-encoded OIDs, tag and length included
+    const SHA256_WITH_RSA: &[u8] = &[
+        0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b,
+    ];
+    const ECDSA_WITH_SHA256: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+    const COMMON_NAME: &[u8] = &[0x06, 0x03, 0x55, 0x04, 0x03];
+    // 0x012345, sent as 74565 in the credential scope
+    const SERIAL: &[u8] = &[0x01, 0x23, 0x45];
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        let length = contents.len().to_be_bytes();
+        let significant = length.iter().skip_while(|byte| **byte == 0).count();
+        if contents.len() < 0x80 {
+            out.push(contents.len() as u8);
+        } else {
+            out.push(0x80 | significant as u8);
+            out.extend_from_slice(&length[length.len() - significant..]);
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    fn sequence(parts: &[&[u8]]) -> Vec<u8> {
+        der(0x30, &parts.concat())
+    }
+
+    // Minimal self-signed v3 certificate for the key behind the PEM file
+    fn self_signed_certificate(key_path: &Path, public_key: &[u8], algorithm: &[u8]) -> Vec<u8> {
+        let key = load_private_key(key_path.to_str().unwrap()).unwrap();
+        let algorithm = match key {
+            SigningKey::Rsa(_) => sequence(&[algorithm, &der(0x05, &[])]),
+            SigningKey::Ecdsa(_) => sequence(&[algorithm]),
+        };
+        let name = sequence(&[&der(
+            0x31,
+            &sequence(&[COMMON_NAME, &der(0x0c, b"aaa-test")]),
+        )]);
+        let validity = sequence(&[&der(0x17, b"250101000000Z"), &der(0x18, b"20991231235959Z")]);
+
+        let tbs = sequence(&[
+            &der(0xa0, &der(0x02, &[2])),
+            &der(0x02, SERIAL),
+            &algorithm,
+            &name,
+            &validity,
+            &name,
+            public_key,
+        ]);
+        let signature = [&[0u8][..], &key.sign(&tbs).unwrap()].concat();
+        sequence(&[&tbs, &algorithm, &der(0x03, &signature)])
+    }
+
+    fn write_pem(path: &Path, label: &str, der: &[u8]) {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(der);
+        let lines = encoded
+            .as_bytes()
+            .chunks(64)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(
+            path,
+            format!(
+                "-----BEGIN {}-----\n{}\n-----END {}-----\n",
+                label, lines, label
+            ),
+        )
+        .unwrap();
+    }
+
+    fn settings(home: &Path, certificate_chain: bool) -> RolesAnywhereSettings {
+        let path = |name: &str| Some(home.join(name).to_str().unwrap().to_string());
+        RolesAnywhereSettings {
+            certificate: path("certificate.pem"),
+            certificate_chain: if certificate_chain {
+                path("certificate.pem")
+            } else {
+                None
+            },
+            private_key: path("key.pem"),
+            trust_anchor_arn: TRUST_ANCHOR_ARN.to_string(),
+            profile_arn: Some(PROFILE_ARN.to_string()),
+            role_arn: Some(ROLE_ARN.to_string()),
+            role_session_name: None,
+            duration_seconds: Some("900".to_string()),
+        }
+    }
+
+    // Checks the request the way IAM Roles Anywhere does: the signature over
+    // the canonical request has to verify with the key of the certificate in
+    // X-Amz-X509, whose serial is the access key of the credential scope
+    fn verify_signature(request: &Received) -> std::result::Result<(), String> {
+        let authorization = request.header("Authorization").ok_or("no Authorization")?;
+        let (algorithm, fields) = authorization.split_once(' ').ok_or("bad Authorization")?;
+        let field = |name: &str| {
+            fields
+                .split(", ")
+                .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+                .ok_or(format!("no {} in Authorization", name))
+        };
+        let (serial, scope) = field("Credential")?
+            .split_once('/')
+            .ok_or("bad Credential")?;
+        let signed_headers = field("SignedHeaders")?;
+        let signature = hex::decode(field("Signature")?).map_err(|e| e.to_string())?;
+
+        let x509 = base64::engine::general_purpose::STANDARD
+            .decode(request.header("X-Amz-X509").ok_or("no X-Amz-X509")?)
+            .map_err(|e| e.to_string())?;
+        let (_, certificate) = X509Certificate::from_der(&x509).map_err(|e| e.to_string())?;
+        if certificate.serial.to_string() != serial {
+            return Err(format!("serial {} is not the certificate's", serial));
+        }
+        if !scope.ends_with("/eu-west-1/rolesanywhere/aws4_request") {
+            return Err(format!("unexpected scope {}", scope));
+        }
+
+        let canonical_headers: String = signed_headers
+            .split(';')
+            .map(|name| format!("{}:{}\n", name, request.header(name).unwrap_or_default()))
+            .collect();
+        let canonical_request = format!(
+            "POST\n{}\n\n{}\n{}\n{}",
+            request.path,
+            canonical_headers,
+            signed_headers,
+            hex_sha256(request.body.as_bytes())
+        );
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            algorithm,
+            request.header("X-Amz-Date").unwrap_or_default(),
+            scope,
+            hex_sha256(canonical_request.as_bytes())
+        );
+
+        let verification: &dyn VerificationAlgorithm = match algorithm {
+            "AWS4-X509-RSA-SHA256" => &RSA_PKCS1_2048_8192_SHA256,
+            "AWS4-X509-ECDSA-SHA256" => &ECDSA_P256_SHA256_ASN1,
+            _ => return Err(format!("unexpected algorithm {}", algorithm)),
+        };
+        UnparsedPublicKey::new(
+            verification,
+            certificate.public_key().subject_public_key.data.as_ref(),
+        )
+        .verify(string_to_sign.as_bytes(), &signature)
+        .map_err(|_| "signature does not verify".to_string())
+    }
+
+    async fn start_roles_anywhere_stand_in() -> StandIn {
+        StandIn::start(|request, _| {
+            if let Err(message) = verify_signature(request) {
+                return Reply::json("403 Forbidden", json!({ "message": message }));
+            }
+            Reply::json(
+                "201 Created",
+                json!({
+                    "credentialSet": [{
+                        "credentials": {
+                            "accessKeyId": "ASIAROLESANYWHERE",
+                            "secretAccessKey": "secret",
+                            "sessionToken": "token",
+                            "expiration": "2099-01-01T00:00:00Z",
+                        }
+                    }]
+                }),
+            )
+        })
+        .await
+    }
+
+    async fn assert_session_created(test_env: &mut TestEnv, certificate_chain: bool) {
+        let roles_anywhere = start_roles_anywhere_stand_in().await;
+        test_env.set(ENDPOINT_URL_ENV, &roles_anywhere.url);
+
+        let credentials = create_session(&settings(&test_env.home, certificate_chain))
+            .await
+            .unwrap();
+        assert_eq!(credentials.access_key_id(), "ASIAROLESANYWHERE");
+        assert_eq!(credentials.session_token(), Some("token"));
+
+        let requests = roles_anywhere.received_at("/sessions");
+        assert_eq!(requests.len(), 1);
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "durationSeconds": 900,
+                "profileArn": PROFILE_ARN,
+                "roleArn": ROLE_ARN,
+                "trustAnchorArn": TRUST_ANCHOR_ARN,
+            })
+        );
+        assert!(requests[0]
+            .header("Authorization")
+            .unwrap()
+            .contains("Credential=74565/"));
+        assert_eq!(
+            requests[0].header("X-Amz-X509-Chain").is_some(),
+            certificate_chain
+        );
+    }
+
+    #[tokio::test]
+    async fn create_session_signs_with_an_rsa_certificate() {
+        let mut test_env = TestEnv::new("roles-anywhere-rsa").await;
+        let key = RsaKeyPair::generate(KeySize::Rsa2048).unwrap();
+        let key_path = test_env.home.join("key.pem");
+        write_pem(&key_path, "PRIVATE KEY", key.as_der().unwrap().as_ref());
+        let certificate = self_signed_certificate(
+            &key_path,
+            key.public_key().as_der().unwrap().as_ref(),
+            SHA256_WITH_RSA,
+        );
+        write_pem(
+            &test_env.home.join("certificate.pem"),
+            "CERTIFICATE",
+            &certificate,
+        );
+
+        assert_session_created(&mut test_env, true).await;
+    }
+
+    #[tokio::test]
+    async fn create_session_signs_with_a_p256_certificate() {
+        let mut test_env = TestEnv::new("roles-anywhere-p256").await;
+        let key = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap();
+        let key_path = test_env.home.join("key.pem");
+        write_pem(
+            &key_path,
+            "EC PRIVATE KEY",
+            key.private_key().as_der().unwrap().as_ref(),
+        );
+        let certificate = self_signed_certificate(
+            &key_path,
+            key.public_key().as_der().unwrap().as_ref(),
+            ECDSA_WITH_SHA256,
+        );
+        write_pem(
+            &test_env.home.join("certificate.pem"),
+            "CERTIFICATE",
+            &certificate,
+        );
+
+        assert_session_created(&mut test_env, false).await;
+    }
+
+    #[tokio::test]
+    async fn create_session_is_rejected_when_the_key_does_not_match() {
+        let mut test_env = TestEnv::new("roles-anywhere-mismatch").await;
+        let certificate_key = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap();
+        let certificate_key_path = test_env.home.join("certificate-key.pem");
+        write_pem(
+            &certificate_key_path,
+            "PRIVATE KEY",
+            certificate_key.to_pkcs8v1().unwrap().as_ref(),
+        );
+        let certificate = self_signed_certificate(
+            &certificate_key_path,
+            certificate_key.public_key().as_der().unwrap().as_ref(),
+            ECDSA_WITH_SHA256,
+        );
+        write_pem(
+            &test_env.home.join("certificate.pem"),
+            "CERTIFICATE",
+            &certificate,
+        );
+        let other_key = EcdsaKeyPair::generate(&ECDSA_P256_SHA256_ASN1_SIGNING).unwrap();
+        write_pem(
+            &test_env.home.join("key.pem"),
+            "PRIVATE KEY",
+            other_key.to_pkcs8v1().unwrap().as_ref(),
+        );
+
+        let roles_anywhere = start_roles_anywhere_stand_in().await;
+        test_env.set(ENDPOINT_URL_ENV, &roles_anywhere.url);
+        let error = create_session(&settings(&test_env.home, false))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("HTTP 403"));
+        assert!(error.to_string().contains("signature does not verify"));
+    }
+}
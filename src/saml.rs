@@ -0,0 +1,392 @@
+use crate::loopback;
+use crate::profile::SamlSettings;
+use crate::sts;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use base64::Engine;
+use colored::Colorize;
+use inquire::{Select, Text};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tokio::net::TcpListener;
+
+const ROLE_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/Role";
+const SESSION_DURATION_ATTRIBUTE: &str = "https://aws.amazon.com/SAML/Attributes/SessionDuration";
+
+// A role/principal pair from the AWS Role attribute of a SAML assertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamlRole {
+    pub role_arn: String,
+    pub principal_arn: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SamlAssertion {
+    // Base64-encoded SAMLResponse, as passed to AssumeRoleWithSAML
+    pub encoded: String,
+    pub roles: Vec<SamlRole>,
+    pub session_duration: Option<i32>,
+}
+
+// Role attribute values are "role_arn,principal_arn", in either order
+fn parse_role_value(value: &str) -> Option<SamlRole> {
+    let (first, second) = value.split_once(',')?;
+    let (first, second) = (first.trim(), second.trim());
+
+    if first.contains(":saml-provider/") {
+        Some(SamlRole {
+            role_arn: second.to_string(),
+            principal_arn: first.to_string(),
+        })
+    } else if second.contains(":saml-provider/") {
+        Some(SamlRole {
+            role_arn: first.to_string(),
+            principal_arn: second.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+pub fn parse_assertion(encoded: &str) -> Result<SamlAssertion> {
+    let encoded: String = encoded.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(&encoded)
+        .context("SAMLResponse is not valid base64")?;
+    let xml = String::from_utf8(decoded).context("SAMLResponse is not valid UTF-8")?;
+
+    let mut reader = Reader::from_str(&xml);
+    reader.config_mut().trim_text(true);
+
+    let mut roles = Vec::new();
+    let mut session_duration = None;
+    let mut attribute_name: Option<String> = None;
+    let mut value: Option<String> = None;
+
+    loop {
+        match reader
+            .read_event()
+            .context("Failed to parse SAML assertion")?
+        {
+            Event::Start(e) if e.local_name().as_ref() == b"Attribute" => {
+                attribute_name = e
+                    .try_get_attribute("Name")?
+                    .map(|name| name.unescape_value().map(|v| v.into_owned()))
+                    .transpose()?;
+            }
+            Event::End(e) if e.local_name().as_ref() == b"Attribute" => {
+                attribute_name = None;
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"AttributeValue" => {
+                value = Some(String::new());
+            }
+            Event::Text(text) => {
+                if let Some(value) = value.as_mut() {
+                    value.push_str(&text.decode()?);
+                }
+            }
+            Event::GeneralRef(reference) => {
+                if let Some(value) = value.as_mut() {
+                    if let Some(c) = reference.resolve_char_ref()? {
+                        value.push(c);
+                    } else {
+                        let entity = format!("&{};", reference.decode()?);
+                        value.push_str(&quick_xml::escape::unescape(&entity)?);
+                    }
+                }
+            }
+            Event::End(e) if e.local_name().as_ref() == b"AttributeValue" => {
+                let Some(value) = value.take() else {
+                    continue;
+                };
+                match attribute_name.as_deref() {
+                    Some(ROLE_ATTRIBUTE) => roles.extend(parse_role_value(&value)),
+                    Some(SESSION_DURATION_ATTRIBUTE) => session_duration = value.parse().ok(),
+                    _ => {}
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(SamlAssertion {
+        encoded,
+        roles,
+        session_duration,
+    })
+}
+
+// Extracts the SAMLResponse form field from an IdP's auto-submit HTML page
+pub fn extract_saml_response(html: &str) -> Result<String> {
+    let position = html
+        .find("name=\"SAMLResponse\"")
+        .ok_or_else(|| anyhow!("No SAMLResponse found in the identity provider response"))?;
+
+    // Look for the value attribute anywhere inside the same <input> tag
+    let tag_start = html[..position].rfind('<').unwrap_or(0);
+    let tag_end = position + html[position..].find('>').unwrap_or(html.len() - position);
+    let tag = &html[tag_start..tag_end];
+
+    let value_start = tag
+        .find("value=\"")
+        .map(|index| index + "value=\"".len())
+        .ok_or_else(|| anyhow!("SAMLResponse field has no value"))?;
+    let value_end = value_start
+        + tag[value_start..]
+            .find('"')
+            .ok_or_else(|| anyhow!("SAMLResponse field has no value"))?;
+
+    let value = quick_xml::escape::unescape(&tag[value_start..value_end])
+        .context("Failed to decode SAMLResponse field")?;
+
+    Ok(value.into_owned())
+}
+
+pub fn select_role(roles: &[SamlRole], preferred_role_arn: Option<&str>) -> Result<SamlRole> {
+    if let Some(preferred_role_arn) = preferred_role_arn {
+        return roles
+            .iter()
+            .find(|role| role.role_arn == preferred_role_arn)
+            .cloned()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Role '{}' is not granted by the SAML assertion",
+                    preferred_role_arn
+                )
+            });
+    }
+
+    match roles {
+        [] => Err(anyhow!("The SAML assertion does not grant any AWS role")),
+        [role] => Ok(role.clone()),
+        _ => {
+            let options: Vec<String> = roles.iter().map(|role| role.role_arn.clone()).collect();
+
+            let selection = Select::new("Select a role:", options)
+                .with_page_size(10)
+                .raw_prompt()
+                .context("Failed to select role")?;
+
+            Ok(roles[selection.index].clone())
+        }
+    }
+}
+
+const CAPTURE_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>aaa - SAML capture</title></head>
+<body style="font-family: sans-serif; max-width: 40em; margin: 2em auto;">
+<h2>aaa - SAML capture</h2>
+<p>Paste the base64-encoded SAMLResponse your identity provider posted to AWS below.</p>
+<form method="post" action="{action}">
+<textarea name="SAMLResponse" rows="12" style="width: 100%;"></textarea>
+<p><button type="submit">Submit</button></p>
+</form>
+</body>
+</html>
+"#;
+
+const CAPTURED_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>aaa - SAML capture</title></head>
+<body style="font-family: sans-serif; max-width: 40em; margin: 2em auto;">
+<h2>SAML assertion received</h2>
+<p>You can close this window and return to your terminal.</p>
+</body>
+</html>
+"#;
+
+// Loopback page the SAMLResponse is posted to. It listens on a random port
+// and only accepts posts to a path carrying a random state token, so other
+// local processes cannot slip in a forged assertion
+struct CaptureListener {
+    listener: TcpListener,
+    path: String,
+    url: String,
+}
+
+async fn start_capture() -> Result<CaptureListener> {
+    let listener = TcpListener::bind(("127.0.0.1", 0))
+        .await
+        .context("Failed to start the SAML capture page")?;
+    let address = listener
+        .local_addr()
+        .context("Failed to start the SAML capture page")?;
+
+    let path = format!("/saml/{}", loopback::random_token()?);
+    let url = format!("http://{}{}", address, path);
+
+    Ok(CaptureListener {
+        listener,
+        path,
+        url,
+    })
+}
+
+async fn wait_for_assertion(capture: &CaptureListener) -> Result<String> {
+    let capture_page = CAPTURE_PAGE.replace("{action}", &capture.path);
+
+    loop {
+        let (mut stream, _) = capture
+            .listener
+            .accept()
+            .await
+            .context("Failed to accept connection")?;
+
+        let request = match loopback::read_request(&mut stream).await {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+
+        if request.path.split('?').next() != Some(capture.path.as_str()) {
+            loopback::write_response(&mut stream, "404 Not Found", loopback::HTML, "Not found")
+                .await?;
+            continue;
+        }
+
+        if request.method != "POST" {
+            loopback::write_response(&mut stream, "200 OK", loopback::HTML, &capture_page).await?;
+            continue;
+        }
+
+        let saml_response = url::form_urlencoded::parse(request.body.as_bytes())
+            .find(|(name, _)| name == "SAMLResponse")
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        match saml_response {
+            Some(saml_response) => {
+                loopback::write_response(&mut stream, "200 OK", loopback::HTML, CAPTURED_PAGE)
+                    .await?;
+                return Ok(saml_response);
+            }
+            None => {
+                loopback::write_response(
+                    &mut stream,
+                    "400 Bad Request",
+                    loopback::HTML,
+                    &capture_page,
+                )
+                .await?;
+            }
+        }
+    }
+}
+
+// Opens the IdP in the browser and waits for the SAMLResponse to be posted to
+// the loopback capture page
+async fn capture_from_browser(idp_url: &str) -> Result<String> {
+    let capture = start_capture().await?;
+
+    println!();
+    println!("Sign in with your identity provider in the browser:");
+    println!();
+    println!("  {}", idp_url.cyan());
+    println!();
+    println!("Then open the capture page and paste the SAMLResponse:");
+    println!();
+    println!("  {}", capture.url.cyan());
+    println!();
+
+    if webbrowser::open(idp_url).is_err() {
+        println!(
+            "{}",
+            "Could not open a browser automatically, please open the URL manually".dimmed()
+        );
+    }
+
+    println!("{}", "Waiting for the SAML assertion...".dimmed());
+
+    wait_for_assertion(&capture).await
+}
+
+fn prompt_for_assertion() -> Result<String> {
+    let saml_response = Text::new("SAMLResponse:")
+        .with_help_message("Paste the base64-encoded SAMLResponse posted by your identity provider")
+        .prompt()
+        .context("Failed to get SAMLResponse")?
+        .trim()
+        .to_string();
+
+    if saml_response.is_empty() {
+        return Err(anyhow!("SAMLResponse cannot be empty"));
+    }
+
+    Ok(saml_response)
+}
+
+pub async fn login(saml: &SamlSettings, region: Option<&str>) -> Result<Credentials> {
+    let saml_response = match &saml.idp_url {
+        Some(idp_url) => capture_from_browser(idp_url).await?,
+        None => prompt_for_assertion()?,
+    };
+
+    let assertion = parse_assertion(&saml_response)?;
+
+    // Narrow the roles down to the configured identity provider, if any
+    let roles: Vec<SamlRole> = match &saml.provider_arn {
+        Some(provider_arn) => assertion
+            .roles
+            .iter()
+            .filter(|role| role.principal_arn == *provider_arn)
+            .cloned()
+            .collect(),
+        None => assertion.roles.clone(),
+    };
+    let role = select_role(&roles, saml.role_arn.as_deref())?;
+
+    println!("{} {}", "Assuming role:".bold(), role.role_arn.green());
+
+    sts::assume_role_with_saml(
+        region,
+        &role.role_arn,
+        &role.principal_arn,
+        &assertion.encoded,
+        assertion.session_duration,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn post(url: &str, path: &str, body: &str) -> String {
+        let address = url.trim_start_matches("http://").split('/').next().unwrap();
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            address,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn capture_only_accepts_posts_with_the_state_token() {
+        let capture = start_capture().await.unwrap();
+        let url = capture.url.clone();
+        let path = capture.path.clone();
+        assert!(path.len() > "/saml/".len() + 32);
+
+        let waiting = tokio::spawn(async move { wait_for_assertion(&capture).await });
+
+        let forged = post(&url, "/saml", "SAMLResponse=forged").await;
+        assert!(forged.starts_with("HTTP/1.1 404"));
+        let guessed = post(&url, "/saml/0000", "SAMLResponse=forged").await;
+        assert!(guessed.starts_with("HTTP/1.1 404"));
+
+        let posted = post(&url, &path, "SAMLResponse=PHNhbWw%2B").await;
+        assert!(posted.starts_with("HTTP/1.1 200"));
+        assert_eq!(waiting.await.unwrap().unwrap(), "PHNhbWw+");
+    }
+}
@@ -0,0 +1,277 @@
+use crate::shell::Shell;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+// Exported to the shell so prompts and `aaa refresh` can find the session
+pub const STATE_FILE_ENV: &str = "AAA_SESSION_FILE";
+// Script with refreshed credentials, sourced by the shell before each prompt
+pub const ENV_FILE_ENV: &str = "AAA_SESSION_ENV";
+// Chain of nested sessions as aaa pids, outermost first: "1200:1234"
+pub const SESSION_ENV: &str = "AAA_SESSION";
+
+// How often the expiry watcher looks for a refreshed session
+const WATCH_INTERVAL_SECONDS: i64 = 30;
+
+// What prompt integrations need to know about the shell's credentials
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionState {
+    pub profile: String,
+    pub region: Option<String>,
+    // None when the credentials do not expire
+    pub expiration: Option<DateTime<Utc>>,
+    pub pid: u32,
+    // Session this one is nested in, and how deep (1 for the outermost)
+    #[serde(default)]
+    pub parent: Option<u32>,
+    #[serde(default)]
+    pub depth: usize,
+    #[serde(default)]
+    pub shell_pid: Option<u32>,
+    // Set when a session started inside this one closed it on exit
+    #[serde(default)]
+    pub closed: bool,
+    // Local credentials endpoint serving the shell, which `aaa refresh` hands
+    // new credentials to instead of the env file
+    #[serde(default)]
+    pub credentials_endpoint: Option<String>,
+}
+
+impl SessionState {
+    pub fn minutes_left(&self) -> Option<i64> {
+        self.expiration
+            .map(|expiration| (expiration - Utc::now()).num_minutes().max(0))
+    }
+}
+
+// The state and env files and the shell startup files of a running shell;
+// all are removed when it exits
+pub struct SessionFiles {
+    pub state_path: PathBuf,
+    pub env_path: PathBuf,
+    pub shell_dir: PathBuf,
+}
+
+impl Drop for SessionFiles {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.state_path);
+        let _ = fs::remove_file(&self.env_path);
+        let _ = fs::remove_dir_all(&self.shell_dir);
+    }
+}
+
+// $XDG_RUNTIME_DIR/aaa, falling back to a per-user temporary directory
+pub fn runtime_dir() -> Result<PathBuf> {
+    if let Some(runtime_dir) = dirs::runtime_dir() {
+        return Ok(runtime_dir.join("aaa"));
+    }
+
+    let path = env::temp_dir().join(format!(
+        "aaa-{}",
+        env::var("USER").unwrap_or_else(|_| "user".to_string())
+    ));
+    create_private_dir(&path)?;
+    check_private_dir(&path)?;
+    Ok(path)
+}
+
+// Anyone may create the fallback directory in the shared temporary directory
+// first, so it is only used when it is ours and closed to everyone else
+#[cfg(unix)]
+fn check_private_dir(path: &Path) -> Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::symlink_metadata(path)
+        .with_context(|| format!("Failed to inspect {}", path.display()))?;
+    // SAFETY: getuid has no preconditions and cannot fail
+    let uid = unsafe { libc::getuid() };
+
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o777 != 0o700 {
+        return Err(anyhow!(
+            "{} must be a directory owned by the current user with mode 0700; remove it or set XDG_RUNTIME_DIR",
+            path.display()
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+pub fn create_private_dir(path: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+
+    builder
+        .create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))
+}
+
+// Session files hold credentials, so only the user may read them; files left
+// readable by others, e.g. by an older version, are tightened as well
+pub fn write_private_file(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict access to {}", path.display()))?;
+    std::io::Write::write_all(&mut file, contents.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn sessions_dir() -> Result<PathBuf> {
+    Ok(runtime_dir()?.join("sessions"))
+}
+
+pub fn state_path(pid: u32) -> Result<PathBuf> {
+    Ok(sessions_dir()?.join(format!("{}.json", pid)))
+}
+
+pub fn create(state: &SessionState) -> Result<SessionFiles> {
+    let directory = sessions_dir()?;
+    create_private_dir(&directory)?;
+
+    let files = SessionFiles {
+        state_path: state_path(state.pid)?,
+        env_path: directory.join(format!("{}.env", state.pid)),
+        shell_dir: directory.join(format!("{}.shell", state.pid)),
+    };
+    create_private_dir(&files.shell_dir)?;
+    save(&files.state_path, state)?;
+
+    Ok(files)
+}
+
+pub fn load(path: &Path) -> Result<SessionState> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read session file {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse session file {}", path.display()))
+}
+
+pub fn save(path: &Path, state: &SessionState) -> Result<()> {
+    let content = serde_json::to_string_pretty(state).context("Failed to serialize session")?;
+    write_private_file(path, &content)
+}
+
+// Sessions this process runs in, outermost first; entries whose session has
+// ended (e.g. AAA_SESSION inherited by a detached tmux server) are skipped
+pub fn chain() -> Vec<u32> {
+    env::var(SESSION_ENV)
+        .unwrap_or_default()
+        .split(':')
+        .filter_map(|pid| pid.parse().ok())
+        .filter(|pid| state_path(*pid).is_ok_and(|path| path.exists()))
+        .collect()
+}
+
+pub fn chain_env(chain: &[u32]) -> String {
+    chain
+        .iter()
+        .map(|pid| pid.to_string())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+// Flags a session as ended by another one: its aaa exits quietly once its
+// shell is gone and stops warning about expiry in the meantime
+pub fn close(state: &SessionState) -> Result<()> {
+    let mut closed = state.clone();
+    closed.closed = true;
+    save(&state_path(state.pid)?, &closed)
+}
+
+// Ends an outer session once the one started inside it has exited
+#[cfg(unix)]
+pub fn hang_up(state: &SessionState) -> Result<()> {
+    let shell_pid = state
+        .shell_pid
+        .ok_or_else(|| anyhow!("The shell of session {} is unknown", state.pid))?;
+
+    close(state)?;
+
+    // SAFETY: sends SIGHUP to the session shell, which interactive shells exit on
+    if unsafe { libc::kill(shell_pid as libc::pid_t, libc::SIGHUP) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .context("Failed to end the outer session's shell");
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn hang_up(_state: &SessionState) -> Result<()> {
+    Err(anyhow!("Closing outer sessions is only supported on Unix"))
+}
+
+// Writes the variables in the syntax of the shell that loads them
+pub fn write_env(path: &Path, shell: Shell, env_vars: &HashMap<String, String>) -> Result<()> {
+    write_private_file(path, &shell.env_script(env_vars))
+}
+
+// Prints a warning to the terminal `minutes` before the session expires, again
+// for every refreshed session, until the returned sender is dropped
+pub fn warn_before_expiry(state_path: PathBuf, minutes: u64) -> mpsc::Sender<()> {
+    let (stop, stopped) = mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        let mut warned_for = None;
+
+        loop {
+            let mut wait = Duration::seconds(WATCH_INTERVAL_SECONDS);
+
+            if let Ok(state) = load(&state_path) {
+                if state.closed {
+                    break;
+                }
+                if let Some(expiration) = state.expiration {
+                    let warn_at = expiration - Duration::minutes(minutes as i64);
+                    let now = Utc::now();
+
+                    if warned_for != Some(expiration) && now >= warn_at {
+                        warned_for = Some(expiration);
+                        eprintln!();
+                        eprintln!(
+                            "{}",
+                            format!(
+                                "aaa: credentials for '{}' expire in {} minutes (at {}). Run 'aaa refresh' to renew them in this shell.",
+                                state.profile,
+                                state.minutes_left().unwrap_or(0),
+                                expiration.to_rfc3339()
+                            )
+                            .yellow()
+                            .bold()
+                        );
+                    } else if warned_for != Some(expiration) {
+                        wait = wait.min(warn_at - now);
+                    }
+                }
+            }
+
+            match stopped.recv_timeout(wait.to_std().unwrap_or_default()) {
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                _ => break,
+            }
+        }
+    });
+
+    stop
+}
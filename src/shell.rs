@@ -0,0 +1,459 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Name of the profile the shell has credentials for, for prompts and starship
+pub const PROFILE_ENV: &str = "AAA_PROFILE";
+// Set to keep aaa from touching the prompt, e.g. when starship shows the profile
+const NO_PROMPT_ENV: &str = "AAA_NO_PROMPT";
+// Where the zsh shim finds the user's own startup files
+const USER_ZDOTDIR_ENV: &str = "AAA_USER_ZDOTDIR";
+
+const BASH_RC: &str = r#"# Generated by aaa for this session
+[ -f ~/.bashrc ] && . ~/.bashrc
+
+_aaa_prompt() {
+    local status=$?
+    [ -r "$AAA_SESSION_ENV" ] && . "$AAA_SESSION_ENV"
+    if [ -z "$AAA_NO_PROMPT" ]; then
+        case "$PS1" in
+            "(aws:$AAA_PROFILE) "*) ;;
+            *) PS1="(aws:$AAA_PROFILE) $PS1" ;;
+        esac
+    fi
+    return $status
+}
+PROMPT_COMMAND="${PROMPT_COMMAND:+$PROMPT_COMMAND
+}_aaa_prompt"
+"#;
+
+const ZSH_ENV: &str = r#"# Generated by aaa for this session
+_aaa_zdotdir="$ZDOTDIR"
+ZDOTDIR="${AAA_USER_ZDOTDIR:-$HOME}"
+[[ -f "$ZDOTDIR/.zshenv" ]] && . "$ZDOTDIR/.zshenv"
+_aaa_user_zdotdir="$ZDOTDIR"
+ZDOTDIR="$_aaa_zdotdir"
+"#;
+
+const ZSH_RC: &str = r#"# Generated by aaa for this session
+ZDOTDIR="$_aaa_user_zdotdir"
+unset _aaa_zdotdir _aaa_user_zdotdir
+[[ -f "$ZDOTDIR/.zshrc" ]] && . "$ZDOTDIR/.zshrc"
+
+_aaa_precmd() {
+    [[ -r "$AAA_SESSION_ENV" ]] && . "$AAA_SESSION_ENV"
+    if [[ -z "$AAA_NO_PROMPT" && "$PROMPT" != "(aws:$AAA_PROFILE) "* ]]; then
+        PROMPT="(aws:$AAA_PROFILE) $PROMPT"
+    fi
+}
+autoload -Uz add-zsh-hook
+add-zsh-hook precmd _aaa_precmd
+"#;
+
+const FISH_INIT: &str = r#"# Generated by aaa for this session
+if functions -q fish_prompt
+    functions -c fish_prompt _aaa_fish_prompt
+else
+    function _aaa_fish_prompt
+        echo -n '> '
+    end
+end
+
+function _aaa_set_status
+    return $argv[1]
+end
+
+function fish_prompt
+    set -l last_status $status
+    test -r "$AAA_SESSION_ENV"; and source "$AAA_SESSION_ENV"
+    if test -z "$AAA_NO_PROMPT"
+        echo -n "(aws:$AAA_PROFILE) "
+    end
+    _aaa_set_status $last_status
+    _aaa_fish_prompt
+end
+"#;
+
+const NUSHELL_INIT: &str = r#"# Generated by aaa for this session
+$env.config.hooks.pre_prompt = (
+    $env.config.hooks.pre_prompt | default [] | append {||
+        if ($env.AAA_SESSION_ENV | path exists) {
+            open --raw $env.AAA_SESSION_ENV | from json | load-env
+        }
+    }
+)
+
+let aaa_prompt = $env.PROMPT_COMMAND? | default ''
+$env.PROMPT_COMMAND = {||
+    let prompt = if ($aaa_prompt | describe) == 'closure' { do $aaa_prompt } else { $aaa_prompt }
+    if ($env.AAA_NO_PROMPT? | is-empty) { '(aws:' + $env.AAA_PROFILE + ') ' + $prompt } else { $prompt }
+}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nushell,
+    // Anything else gets PS1 and has to source the env file by hand
+    Other,
+}
+
+impl Shell {
+    pub fn detect(shell_path: &str) -> Shell {
+        let name = Path::new(shell_path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default()
+            .trim_start_matches('-');
+
+        match name {
+            "bash" => Shell::Bash,
+            "zsh" => Shell::Zsh,
+            "fish" => Shell::Fish,
+            "nu" | "nushell" => Shell::Nushell,
+            _ => Shell::Other,
+        }
+    }
+
+    pub fn from_env() -> Shell {
+        Shell::detect(&env::var("SHELL").unwrap_or_default())
+    }
+
+    // Whether the shell picks up refreshed credentials on its own
+    pub fn reloads_env(&self) -> bool {
+        *self != Shell::Other
+    }
+
+    // Script that sets the variables when loaded by this shell; nushell loads
+    // JSON because it cannot source a file chosen at runtime
+    pub fn env_script(&self, env_vars: &HashMap<String, String>) -> String {
+        let format = match self {
+            Shell::Fish => EnvFormat::Fish,
+            Shell::Nushell => EnvFormat::Json,
+            Shell::Bash | Shell::Zsh | Shell::Other => EnvFormat::Bash,
+        };
+        format.script(env_vars, &[])
+    }
+
+    // Sets the shell up to show the profile in its prompt and reload refreshed
+    // credentials, with startup files written to `dir`
+    pub fn integrate(&self, command: &mut Command, dir: &Path, profile_name: &str) -> Result<()> {
+        match self {
+            Shell::Bash => {
+                let rc_path = dir.join("bashrc");
+                write_file(&rc_path, BASH_RC)?;
+                command.arg("--rcfile").arg(&rc_path);
+            }
+            Shell::Zsh => {
+                write_file(&dir.join(".zshenv"), ZSH_ENV)?;
+                write_file(&dir.join(".zshrc"), ZSH_RC)?;
+
+                let user_zdotdir = env::var_os("ZDOTDIR")
+                    .or_else(|| dirs::home_dir().map(|home| home.into_os_string()))
+                    .unwrap_or_default();
+                command.env(USER_ZDOTDIR_ENV, user_zdotdir);
+                command.env("ZDOTDIR", dir);
+            }
+            Shell::Fish => {
+                let init_path = dir.join("init.fish");
+                write_file(&init_path, FISH_INIT)?;
+                command.arg("--init-command").arg(format!(
+                    "source {}",
+                    fish_quote(&init_path.to_string_lossy())
+                ));
+            }
+            Shell::Nushell => {
+                let init_path = dir.join("init.nu");
+                write_file(&init_path, NUSHELL_INIT)?;
+                command.arg("--execute").arg(format!(
+                    "source {}",
+                    nushell_quote(&init_path.to_string_lossy())
+                ));
+            }
+            Shell::Other => {
+                if env::var_os(NO_PROMPT_ENV).is_none() {
+                    let ps1_prefix = format!("(aws:{}) ", profile_name);
+                    if let Ok(current_ps1) = env::var("PS1") {
+                        command.env("PS1", format!("{}{}", ps1_prefix, current_ps1));
+                    } else {
+                        command.env("PS1", format!("{}\\$ ", ps1_prefix));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Output formats of `aaa env`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum EnvFormat {
+    // Also understood by zsh and other POSIX shells
+    Bash,
+    Fish,
+    Powershell,
+    Nushell,
+    // KEY=value lines for docker-compose env_file and similar
+    Dotenv,
+    Json,
+}
+
+impl EnvFormat {
+    // Whether the output is a script that can also clear variables
+    pub fn can_unset(&self) -> bool {
+        !matches!(self, EnvFormat::Dotenv | EnvFormat::Json)
+    }
+
+    // Statements that clear `unset` and then set `env_vars`
+    pub fn script(&self, env_vars: &HashMap<String, String>, unset: &[&str]) -> String {
+        let mut keys: Vec<&String> = env_vars.keys().collect();
+        keys.sort();
+
+        let unset_lines: String = unset
+            .iter()
+            .map(|key| match self {
+                EnvFormat::Bash => format!("unset {}\n", key),
+                EnvFormat::Fish => format!("set -e {}\n", key),
+                EnvFormat::Powershell => {
+                    format!("Remove-Item Env:{} -ErrorAction SilentlyContinue\n", key)
+                }
+                EnvFormat::Nushell => format!("hide-env -i {}\n", key),
+                EnvFormat::Dotenv | EnvFormat::Json => String::new(),
+            })
+            .collect();
+
+        let set_lines: String = match self {
+            EnvFormat::Bash => keys
+                .into_iter()
+                .map(|key| format!("export {}={}\n", key, posix_quote(&env_vars[key])))
+                .collect(),
+            EnvFormat::Fish => keys
+                .into_iter()
+                .map(|key| format!("set -gx {} {}\n", key, fish_quote(&env_vars[key])))
+                .collect(),
+            EnvFormat::Powershell => keys
+                .into_iter()
+                .map(|key| format!("$env:{} = {}\n", key, powershell_quote(&env_vars[key])))
+                .collect(),
+            EnvFormat::Nushell if keys.is_empty() => String::new(),
+            EnvFormat::Nushell => {
+                let fields: Vec<String> = keys
+                    .into_iter()
+                    .map(|key| format!("    {}: {}", key, nushell_quote(&env_vars[key])))
+                    .collect();
+                format!("load-env {{\n{}\n}}\n", fields.join("\n"))
+            }
+            EnvFormat::Dotenv => keys
+                .into_iter()
+                .map(|key| format!("{}={}\n", key, dotenv_quote(&env_vars[key])))
+                .collect(),
+            EnvFormat::Json => {
+                let sorted: std::collections::BTreeMap<&String, &String> =
+                    env_vars.iter().collect();
+                format!(
+                    "{}\n",
+                    serde_json::to_string_pretty(&sorted).unwrap_or_default()
+                )
+            }
+        };
+
+        unset_lines + &set_lines
+    }
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+pub fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+pub fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+// Plain values are taken literally, single quotes keep the rest from being
+// interpolated; only values containing quotes or newlines need escaping
+pub fn dotenv_quote(value: &str) -> String {
+    let plain = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+-./:=@_,".contains(c));
+
+    if plain {
+        value.to_string()
+    } else if !value.contains(['\'', '\n']) {
+        format!("'{}'", value)
+    } else {
+        format!(
+            "\"{}\"",
+            value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n")
+        )
+    }
+}
+
+// Raw strings need no escaping as long as the delimiter does not occur inside
+pub fn nushell_quote(value: &str) -> String {
+    let mut hashes = "#".to_string();
+    while value.contains(&format!("'{}", hashes)) {
+        hashes.push('#');
+    }
+    format!("r{}'{}'{}", hashes, value, hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Values with quotes, $, backslashes, newlines and nothing at all
+    const VALUES: [&str; 6] = ["it's", "$HOME", "a\\b", "a\nb", "", "say \"hi\""];
+
+    fn assert_quotes(quote: fn(&str) -> String, expected: [&str; 6]) {
+        for (value, expected) in VALUES.into_iter().zip(expected) {
+            assert_eq!(quote(value), expected, "quoting {:?}", value);
+        }
+    }
+
+    #[test]
+    fn posix_values_are_single_quoted() {
+        assert_quotes(
+            posix_quote,
+            [
+                r"'it'\''s'",
+                "'$HOME'",
+                r"'a\b'",
+                "'a\nb'",
+                "''",
+                "'say \"hi\"'",
+            ],
+        );
+    }
+
+    #[test]
+    fn fish_values_escape_quotes_and_backslashes() {
+        assert_quotes(
+            fish_quote,
+            [
+                r"'it\'s'",
+                "'$HOME'",
+                r"'a\\b'",
+                "'a\nb'",
+                "''",
+                "'say \"hi\"'",
+            ],
+        );
+    }
+
+    #[test]
+    fn powershell_values_double_single_quotes() {
+        assert_quotes(
+            powershell_quote,
+            [
+                "'it''s'",
+                "'$HOME'",
+                r"'a\b'",
+                "'a\nb'",
+                "''",
+                "'say \"hi\"'",
+            ],
+        );
+    }
+
+    #[test]
+    fn nushell_values_are_raw_strings() {
+        assert_quotes(
+            nushell_quote,
+            [
+                "r#'it's'#",
+                "r#'$HOME'#",
+                r"r#'a\b'#",
+                "r#'a\nb'#",
+                "r#''#",
+                "r#'say \"hi\"'#",
+            ],
+        );
+        // The delimiter grows until it does not occur in the value
+        assert_eq!(nushell_quote("a'#b"), "r##'a'#b'##");
+    }
+
+    #[test]
+    fn dotenv_values_are_quoted_only_when_needed() {
+        assert_quotes(
+            dotenv_quote,
+            [
+                "\"it's\"",
+                "'$HOME'",
+                r"'a\b'",
+                r#""a\nb""#,
+                "",
+                "'say \"hi\"'",
+            ],
+        );
+        assert_eq!(dotenv_quote("eu-west-1"), "eu-west-1");
+        assert_eq!(dotenv_quote("it's \"x\"\\"), r#""it's \"x\"\\""#);
+    }
+
+    #[test]
+    fn scripts_unset_stale_variables_in_each_syntax() {
+        let env_vars = HashMap::from([("AWS_REGION".to_string(), "eu-west-1".to_string())]);
+        let unset = ["AWS_SESSION_TOKEN"];
+
+        let expected = [
+            (
+                EnvFormat::Bash,
+                "unset AWS_SESSION_TOKEN\nexport AWS_REGION='eu-west-1'\n",
+            ),
+            (
+                EnvFormat::Fish,
+                "set -e AWS_SESSION_TOKEN\nset -gx AWS_REGION 'eu-west-1'\n",
+            ),
+            (
+                EnvFormat::Powershell,
+                "Remove-Item Env:AWS_SESSION_TOKEN -ErrorAction SilentlyContinue\n$env:AWS_REGION = 'eu-west-1'\n",
+            ),
+            (
+                EnvFormat::Nushell,
+                "hide-env -i AWS_SESSION_TOKEN\nload-env {\n    AWS_REGION: r#'eu-west-1'#\n}\n",
+            ),
+            // Files cannot clear variables
+            (EnvFormat::Dotenv, "AWS_REGION=eu-west-1\n"),
+            (EnvFormat::Json, "{\n  \"AWS_REGION\": \"eu-west-1\"\n}\n"),
+        ];
+
+        for (format, expected) in expected {
+            assert_eq!(format.script(&env_vars, &unset), expected, "{:?}", format);
+        }
+    }
+
+    // The quoted values survive a real POSIX shell unchanged
+    #[cfg(unix)]
+    #[test]
+    fn posix_script_round_trips_through_sh() {
+        for value in VALUES {
+            let env_vars = HashMap::from([("AAA_TEST_VALUE".to_string(), value.to_string())]);
+            let script = EnvFormat::Bash.script(&env_vars, &[]);
+
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(format!("{}printf %s \"$AAA_TEST_VALUE\"", script))
+                .env("HOME", "/home/test")
+                .output()
+                .unwrap();
+
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), value);
+        }
+    }
+}
@@ -0,0 +1,645 @@
+use crate::agent;
+use crate::profile::SsoSettings;
+use crate::session;
+use crate::sts;
+use anyhow::{anyhow, Context, Result};
+use aws_credential_types::Credentials;
+use chrono::{DateTime, Utc};
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+const CLIENT_NAME: &str = "aaa";
+const CLIENT_TYPE: &str = "public";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+// Cached tokens this close to expiry are renewed instead of reused
+const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+// Token cache entry, in the same format the AWS CLI v2 writes to ~/.aws/sso/cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoToken {
+    pub start_url: String,
+    pub region: String,
+    pub access_token: String,
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_expires_at: Option<String>,
+}
+
+impl SsoToken {
+    pub fn expiration(&self) -> Option<DateTime<Utc>> {
+        parse_timestamp(&self.expires_at)
+    }
+}
+
+// An account/role combination the SSO user has access to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountRole {
+    pub account_id: String,
+    pub account_name: String,
+    pub role_name: String,
+}
+
+// Client registration cache entry (botocore-client-id-<region>.json)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientRegistration {
+    client_id: String,
+    client_secret: String,
+    expires_at: String,
+}
+
+fn get_sso_cache_dir() -> Result<PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
+    Ok(home.join(".aws").join("sso").join("cache"))
+}
+
+// The CLI keys the token cache by the session name for [sso-session] based
+// profiles and by the start URL for legacy ones
+fn cache_key(sso: &SsoSettings) -> Option<&str> {
+    sso.session.as_deref().or(sso.start_url.as_deref())
+}
+
+// Cache files are named after the SHA-1 of the cache key
+fn get_token_cache_path(cache_key: &str) -> Result<PathBuf> {
+    let digest = Sha1::digest(cache_key.as_bytes());
+    Ok(get_sso_cache_dir()?.join(format!("{:x}.json", digest)))
+}
+
+fn format_timestamp(timestamp: DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    // Older CLI versions wrote a "UTC" suffix instead of "Z"
+    let normalized = match value.strip_suffix("UTC") {
+        Some(prefix) => format!("{}Z", prefix),
+        None => value.to_string(),
+    };
+
+    DateTime::parse_from_rfc3339(&normalized)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn is_expired(expires_at: &str) -> bool {
+    match parse_timestamp(expires_at) {
+        Some(timestamp) => timestamp <= Utc::now(),
+        None => true,
+    }
+}
+
+// Cache entries hold access tokens and client secrets, so like the AWS CLI
+// only the user may read them
+fn write_cache_file<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create SSO cache directory")?;
+    }
+
+    let content = serde_json::to_string(value).context("Failed to serialize SSO cache entry")?;
+    session::write_private_file(path, &content).context("Failed to write SSO cache file")
+}
+
+pub fn load_cached_token(cache_key: &str) -> Result<Option<SsoToken>> {
+    let path = get_token_cache_path(cache_key)?;
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = fs::read_to_string(&path).context("Failed to read SSO token cache")?;
+    let token: SsoToken =
+        serde_json::from_str(&content).context("Failed to parse SSO token cache")?;
+
+    Ok(Some(token))
+}
+
+fn save_cached_token(cache_key: &str, token: &SsoToken) -> Result<()> {
+    write_cache_file(&get_token_cache_path(cache_key)?, token)
+}
+
+// A running aaa agent holds the token in memory; otherwise it is in the cache
+// shared with the AWS CLI
+async fn load_token(sso: &SsoSettings) -> Result<Option<SsoToken>> {
+    if let Some(start_url) = &sso.start_url {
+        if let Some(token) = agent::get_token(start_url).await {
+            return Ok(Some(token));
+        }
+    }
+
+    match cache_key(sso) {
+        Some(cache_key) => load_cached_token(cache_key),
+        None => Ok(None),
+    }
+}
+
+async fn register_new_client(
+    client: &aws_sdk_ssooidc::Client,
+    scopes: Option<&str>,
+) -> Result<ClientRegistration> {
+    let mut request = client
+        .register_client()
+        .client_name(CLIENT_NAME)
+        .client_type(CLIENT_TYPE);
+
+    for scope in scopes
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|scope| !scope.is_empty())
+    {
+        request = request.scopes(scope);
+    }
+
+    let output = request
+        .send()
+        .await
+        .context("Failed to register SSO OIDC client")?;
+
+    let expires_at = DateTime::from_timestamp(output.client_secret_expires_at(), 0)
+        .ok_or_else(|| anyhow!("Invalid client registration expiry"))?;
+
+    let registration = ClientRegistration {
+        client_id: output
+            .client_id()
+            .ok_or_else(|| anyhow!("RegisterClient returned no client ID"))?
+            .to_string(),
+        client_secret: output
+            .client_secret()
+            .ok_or_else(|| anyhow!("RegisterClient returned no client secret"))?
+            .to_string(),
+        expires_at: format_timestamp(expires_at),
+    };
+
+    Ok(registration)
+}
+
+async fn register_client(
+    client: &aws_sdk_ssooidc::Client,
+    sso: &SsoSettings,
+    region: &str,
+) -> Result<ClientRegistration> {
+    // Session based profiles keep the registration inside the token cache entry
+    if sso.session.is_some() {
+        if let Some(token) = load_token(sso).await? {
+            if let (Some(client_id), Some(client_secret), Some(expires_at)) = (
+                token.client_id,
+                token.client_secret,
+                token.registration_expires_at,
+            ) {
+                if !is_expired(&expires_at) {
+                    return Ok(ClientRegistration {
+                        client_id,
+                        client_secret,
+                        expires_at,
+                    });
+                }
+            }
+        }
+
+        return register_new_client(client, sso.registration_scopes.as_deref()).await;
+    }
+
+    let path = get_sso_cache_dir()?.join(format!("botocore-client-id-{}.json", region));
+
+    // Reuse the cached registration while it is still valid
+    if path.exists() {
+        let content =
+            fs::read_to_string(&path).context("Failed to read SSO client registration")?;
+        if let Ok(registration) = serde_json::from_str::<ClientRegistration>(&content) {
+            if !is_expired(&registration.expires_at) {
+                return Ok(registration);
+            }
+        }
+    }
+
+    let registration = register_new_client(client, None).await?;
+    write_cache_file(&path, &registration)?;
+
+    Ok(registration)
+}
+
+fn missing_session_error(sso: &SsoSettings) -> anyhow::Error {
+    match &sso.session {
+        Some(session) => anyhow!(
+            "SSO session '{}' is not defined or incomplete in the AWS config file",
+            session
+        ),
+        None => anyhow!("SSO start URL is required but not configured"),
+    }
+}
+
+pub async fn login(sso: &SsoSettings) -> Result<SsoToken> {
+    let start_url = sso
+        .start_url
+        .as_deref()
+        .ok_or_else(|| missing_session_error(sso))?;
+    let sso_region = sso
+        .region
+        .as_deref()
+        .ok_or_else(|| missing_session_error(sso))?;
+    let cache_key = cache_key(sso).unwrap_or(start_url);
+
+    let config = sts::client_config(sso_region, None, "AWS_ENDPOINT_URL_SSO_OIDC");
+    let client = aws_sdk_ssooidc::Client::new(&config);
+
+    let registration = register_client(&client, sso, sso_region).await?;
+
+    let authorization = client
+        .start_device_authorization()
+        .client_id(&registration.client_id)
+        .client_secret(&registration.client_secret)
+        .start_url(start_url)
+        .send()
+        .await
+        .context("Failed to start SSO device authorization")?;
+
+    let device_code = authorization
+        .device_code()
+        .ok_or_else(|| anyhow!("StartDeviceAuthorization returned no device code"))?;
+    let verification_uri = authorization
+        .verification_uri_complete()
+        .or(authorization.verification_uri())
+        .ok_or_else(|| anyhow!("StartDeviceAuthorization returned no verification URI"))?;
+
+    println!();
+    println!("Open the following URL to authorize this device:");
+    println!();
+    println!("  {}", verification_uri.cyan());
+    println!();
+    if let Some(user_code) = authorization.user_code() {
+        println!(
+            "Confirm that the code shown in the browser is: {}",
+            user_code.bold()
+        );
+        println!();
+    }
+
+    if webbrowser::open(verification_uri).is_err() {
+        println!(
+            "{}",
+            "Could not open a browser automatically, please open the URL manually".dimmed()
+        );
+    }
+
+    println!("{}", "Waiting for authorization...".dimmed());
+
+    let mut interval = Duration::from_secs(authorization.interval().max(1) as u64);
+    let deadline = Instant::now() + Duration::from_secs(authorization.expires_in().max(0) as u64);
+
+    let output = loop {
+        if Instant::now() >= deadline {
+            return Err(anyhow!(
+                "SSO device authorization expired before it was approved"
+            ));
+        }
+
+        tokio::time::sleep(interval).await;
+
+        match client
+            .create_token()
+            .client_id(&registration.client_id)
+            .client_secret(&registration.client_secret)
+            .grant_type(DEVICE_CODE_GRANT_TYPE)
+            .device_code(device_code)
+            .send()
+            .await
+        {
+            Ok(output) => break output,
+            Err(err) => {
+                let service_error = err.into_service_error();
+                if service_error.is_authorization_pending_exception() {
+                    continue;
+                }
+                if service_error.is_slow_down_exception() {
+                    interval += Duration::from_secs(5);
+                    continue;
+                }
+                return Err(anyhow!(service_error).context("Failed to create SSO token"));
+            }
+        }
+    };
+
+    let expires_at = Utc::now() + chrono::Duration::seconds(output.expires_in() as i64);
+
+    let token = SsoToken {
+        start_url: start_url.to_string(),
+        region: sso_region.to_string(),
+        access_token: output
+            .access_token()
+            .ok_or_else(|| anyhow!("CreateToken returned no access token"))?
+            .to_string(),
+        expires_at: format_timestamp(expires_at),
+        refresh_token: output.refresh_token().map(String::from),
+        client_id: Some(registration.client_id.clone()),
+        client_secret: Some(registration.client_secret.clone()),
+        registration_expires_at: Some(registration.expires_at.clone()),
+    };
+
+    // With the aaa agent running the token stays in its memory only
+    if !agent::store_token(start_url, &token).await {
+        save_cached_token(cache_key, &token)?;
+    }
+
+    Ok(token)
+}
+
+// Returns the cached token when it stays valid for a while, so login can be skipped
+pub async fn load_fresh_token(sso: &SsoSettings) -> Result<Option<SsoToken>> {
+    let refresh_at = Utc::now() + chrono::Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES);
+    Ok(load_token(sso).await?.filter(|token| {
+        token
+            .expiration()
+            .is_some_and(|expires_at| expires_at > refresh_at)
+    }))
+}
+
+async fn load_valid_token(profile_name: &str, sso: &SsoSettings) -> Result<SsoToken> {
+    if cache_key(sso).is_none() {
+        return Err(missing_session_error(sso));
+    }
+
+    match load_token(sso).await? {
+        Some(token) if !is_expired(&token.expires_at) => Ok(token),
+        _ => Err(anyhow!(
+            "SSO session for '{}' is missing or expired. Run 'aaa {}' to log in again.",
+            profile_name,
+            profile_name
+        )),
+    }
+}
+
+pub async fn list_account_roles(profile_name: &str, sso: &SsoSettings) -> Result<Vec<AccountRole>> {
+    let token = load_valid_token(profile_name, sso).await?;
+
+    let config = sts::client_config(&token.region, None, "AWS_ENDPOINT_URL_SSO");
+    let client = aws_sdk_sso::Client::new(&config);
+
+    let mut accounts = Vec::new();
+    let mut next_token = None;
+    loop {
+        let output = client
+            .list_accounts()
+            .access_token(&token.access_token)
+            .set_next_token(next_token)
+            .send()
+            .await
+            .context("Failed to list SSO accounts")?;
+
+        accounts.extend(output.account_list().iter().cloned());
+
+        next_token = output.next_token().map(String::from);
+        if next_token.is_none() {
+            break;
+        }
+    }
+
+    let mut account_roles = Vec::new();
+    for account in accounts {
+        let Some(account_id) = account.account_id() else {
+            continue;
+        };
+        let account_name = account.account_name().unwrap_or(account_id);
+
+        let mut next_token = None;
+        loop {
+            let output = client
+                .list_account_roles()
+                .access_token(&token.access_token)
+                .account_id(account_id)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .with_context(|| format!("Failed to list roles for account {}", account_id))?;
+
+            for role in output.role_list() {
+                if let Some(role_name) = role.role_name() {
+                    account_roles.push(AccountRole {
+                        account_id: account_id.to_string(),
+                        account_name: account_name.to_string(),
+                        role_name: role_name.to_string(),
+                    });
+                }
+            }
+
+            next_token = output.next_token().map(String::from);
+            if next_token.is_none() {
+                break;
+            }
+        }
+    }
+
+    Ok(account_roles)
+}
+
+pub async fn get_role_credentials(profile_name: &str, sso: &SsoSettings) -> Result<Credentials> {
+    let account_id = sso
+        .account_id
+        .as_deref()
+        .ok_or_else(|| anyhow!("SSO account ID is required but not configured"))?;
+    let role_name = sso
+        .role_name
+        .as_deref()
+        .ok_or_else(|| anyhow!("SSO role name is required but not configured"))?;
+
+    let token = load_valid_token(profile_name, sso).await?;
+
+    let config = sts::client_config(&token.region, None, "AWS_ENDPOINT_URL_SSO");
+    let client = aws_sdk_sso::Client::new(&config);
+
+    let output = client
+        .get_role_credentials()
+        .account_id(account_id)
+        .role_name(role_name)
+        .access_token(&token.access_token)
+        .send()
+        .await
+        .context("Failed to get SSO role credentials")?;
+
+    let role_credentials = output
+        .role_credentials()
+        .ok_or_else(|| anyhow!("GetRoleCredentials returned no credentials"))?;
+
+    let expiration =
+        UNIX_EPOCH + Duration::from_millis(role_credentials.expiration().max(0) as u64);
+
+    Ok(Credentials::new(
+        role_credentials
+            .access_key_id()
+            .ok_or_else(|| anyhow!("GetRoleCredentials returned no access key ID"))?,
+        role_credentials
+            .secret_access_key()
+            .ok_or_else(|| anyhow!("GetRoleCredentials returned no secret access key"))?,
+        role_credentials.session_token().map(String::from),
+        Some(expiration),
+        "aaa-sso",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Reply, StandIn, TestEnv};
+    use serde_json::json;
+
+    fn session_settings() -> SsoSettings {
+        SsoSettings {
+            session: Some("corp".to_string()),
+            start_url: Some("https://corp.awsapps.com/start".to_string()),
+            region: Some("eu-west-1".to_string()),
+            registration_scopes: Some("sso:account:access".to_string()),
+            account_id: Some("111111111111".to_string()),
+            role_name: Some("Admin".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn device_flow_polls_until_approved_and_backs_off() {
+        let mut test_env = TestEnv::new("sso-device-flow").await;
+        let oidc = StandIn::start(|request, earlier| match request.path.as_str() {
+            "/client/register" => Reply::json(
+                "200 OK",
+                json!({
+                    "clientId": "client-id",
+                    "clientSecret": "client-secret",
+                    "clientSecretExpiresAt": Utc::now().timestamp() + 86400,
+                }),
+            ),
+            "/device_authorization" => Reply::json(
+                "200 OK",
+                json!({
+                    "deviceCode": "device-code",
+                    "userCode": "ABCD-EFGH",
+                    "verificationUri": "https://device.sso.eu-west-1.amazonaws.com/",
+                    "expiresIn": 600,
+                    "interval": 1,
+                }),
+            ),
+            "/token" => match earlier.iter().filter(|r| r.path == "/token").count() {
+                0 => Reply::json(
+                    "400 Bad Request",
+                    json!({ "__type": "AuthorizationPendingException", "error": "authorization_pending" }),
+                ),
+                1 => Reply::json(
+                    "400 Bad Request",
+                    json!({ "__type": "SlowDownException", "error": "slow_down" }),
+                ),
+                _ => Reply::json(
+                    "200 OK",
+                    json!({
+                        "accessToken": "access-token",
+                        "tokenType": "Bearer",
+                        "expiresIn": 3600,
+                        "refreshToken": "refresh-token",
+                    }),
+                ),
+            },
+            _ => Reply::json("404 Not Found", json!({})),
+        })
+        .await;
+        test_env.set("AWS_ENDPOINT_URL_SSO_OIDC", &oidc.url);
+
+        let sso = session_settings();
+        let token = login(&sso).await.unwrap();
+
+        assert_eq!(token.access_token, "access-token");
+        assert_eq!(token.refresh_token.as_deref(), Some("refresh-token"));
+        assert_eq!(token.client_id.as_deref(), Some("client-id"));
+
+        let register = oidc.received_at("/client/register");
+        assert_eq!(register.len(), 1);
+        assert!(register[0].body.contains("sso:account:access"));
+
+        // Pending keeps the interval, slow_down adds five seconds to it
+        let polls = oidc.received_at("/token");
+        assert_eq!(polls.len(), 3);
+        assert!(polls[1].at - polls[0].at < Duration::from_secs(3));
+        assert!(polls[2].at - polls[1].at >= Duration::from_secs(6));
+        assert!(polls[2].body.contains(DEVICE_CODE_GRANT_TYPE));
+
+        let cached = load_fresh_token(&sso).await.unwrap().unwrap();
+        assert_eq!(cached.access_token, "access-token");
+        assert_eq!(
+            cached.registration_expires_at,
+            token.registration_expires_at
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let path = get_token_cache_path("corp").unwrap();
+            let mode = fs::metadata(path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    #[tokio::test]
+    async fn role_credentials_use_the_cached_token() {
+        let mut test_env = TestEnv::new("sso-role-credentials").await;
+        let portal = StandIn::start(|request, _| {
+            if !request.path.starts_with("/federation/credentials?")
+                || request.header("x-amz-sso_bearer_token") != Some("access-token")
+            {
+                return Reply::json("403 Forbidden", json!({ "message": "denied" }));
+            }
+            Reply::json(
+                "200 OK",
+                json!({
+                    "roleCredentials": {
+                        "accessKeyId": "ASIASSO",
+                        "secretAccessKey": "secret",
+                        "sessionToken": "session-token",
+                        "expiration": 1_893_456_000_000_i64,
+                    }
+                }),
+            )
+        })
+        .await;
+        test_env.set("AWS_ENDPOINT_URL_SSO", &portal.url);
+
+        let sso = session_settings();
+        assert!(get_role_credentials("corp-admin", &sso)
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("missing or expired"));
+
+        save_cached_token(
+            "corp",
+            &SsoToken {
+                start_url: "https://corp.awsapps.com/start".to_string(),
+                region: "eu-west-1".to_string(),
+                access_token: "access-token".to_string(),
+                expires_at: format_timestamp(Utc::now() + chrono::Duration::hours(1)),
+                refresh_token: None,
+                client_id: None,
+                client_secret: None,
+                registration_expires_at: None,
+            },
+        )
+        .unwrap();
+
+        let credentials = get_role_credentials("corp-admin", &sso).await.unwrap();
+        assert_eq!(credentials.access_key_id(), "ASIASSO");
+        assert_eq!(credentials.session_token(), Some("session-token"));
+        assert_eq!(
+            credentials.expiry(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_893_456_000))
+        );
+
+        let requests = portal.received_at("/federation/credentials");
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].path.contains("account_id=111111111111"));
+        assert!(requests[0].path.contains("role_name=Admin"));
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, Context, Result};
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_credential_types::Credentials;
+use aws_types::region::Region;
+use std::time::SystemTime;
+
+// STS is available in every region; fall back to us-east-1 when the profile has none
+const DEFAULT_STS_REGION: &str = "us-east-1";
+
+pub struct AssumeRoleRequest<'a> {
+    pub role_arn: &'a str,
+    pub role_session_name: &'a str,
+    pub external_id: Option<&'a str>,
+    pub duration_seconds: Option<i32>,
+    pub mfa_serial: Option<&'a str>,
+    pub token_code: Option<&'a str>,
+}
+
+// Config for the clients used while logging in. It is built without the
+// aws_config loader, which would apply the active profile's endpoint_url and
+// services settings and AWS_ENDPOINT_URL*, sending the login to an endpoint
+// the profile itself chose
+pub fn client_config(
+    region: &str,
+    credentials: Option<Credentials>,
+    endpoint_url_env: &str,
+) -> SdkConfig {
+    let mut builder = SdkConfig::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new(region.to_string()));
+    if let Some(credentials) = credentials {
+        builder = builder.credentials_provider(SharedCredentialsProvider::new(credentials));
+    }
+    builder.set_endpoint_url(test_endpoint_url(endpoint_url_env));
+    builder.build()
+}
+
+// The tests point the clients at a loopback stand-in through the service's
+// AWS_ENDPOINT_URL_* variable
+#[cfg(test)]
+fn test_endpoint_url(endpoint_url_env: &str) -> Option<String> {
+    std::env::var(endpoint_url_env).ok()
+}
+
+#[cfg(not(test))]
+fn test_endpoint_url(_endpoint_url_env: &str) -> Option<String> {
+    None
+}
+
+fn sdk_config(region: Option<&str>, credentials: Option<Credentials>) -> SdkConfig {
+    client_config(
+        region.unwrap_or(DEFAULT_STS_REGION),
+        credentials,
+        "AWS_ENDPOINT_URL_STS",
+    )
+}
+
+fn convert_credentials(
+    credentials: Option<&aws_sdk_sts::types::Credentials>,
+    provider_name: &'static str,
+) -> Result<Credentials> {
+    let credentials = credentials.ok_or_else(|| anyhow!("STS returned no credentials"))?;
+
+    let expiration = SystemTime::try_from(*credentials.expiration())
+        .context("STS returned an invalid credential expiration")?;
+
+    Ok(Credentials::new(
+        credentials.access_key_id(),
+        credentials.secret_access_key(),
+        Some(credentials.session_token().to_string()),
+        Some(expiration),
+        provider_name,
+    ))
+}
+
+pub fn default_role_session_name() -> String {
+    format!("aaa-{}", chrono::Utc::now().timestamp())
+}
+
+pub async fn assume_role(
+    base_credentials: Credentials,
+    region: Option<&str>,
+    request: AssumeRoleRequest<'_>,
+) -> Result<Credentials> {
+    let config = sdk_config(region, Some(base_credentials));
+    let client = aws_sdk_sts::Client::new(&config);
+
+    let output = client
+        .assume_role()
+        .role_arn(request.role_arn)
+        .role_session_name(request.role_session_name)
+        .set_external_id(request.external_id.map(String::from))
+        .set_duration_seconds(request.duration_seconds)
+        .set_serial_number(request.mfa_serial.map(String::from))
+        .set_token_code(request.token_code.map(String::from))
+        .send()
+        .await
+        .with_context(|| format!("Failed to assume role '{}'", request.role_arn))?;
+
+    convert_credentials(output.credentials(), "aaa-assume-role")
+}
+
+pub async fn get_session_token(
+    base_credentials: Credentials,
+    region: Option<&str>,
+    mfa_serial: &str,
+    token_code: &str,
+) -> Result<Credentials> {
+    let config = sdk_config(region, Some(base_credentials));
+    let client = aws_sdk_sts::Client::new(&config);
+
+    let output = client
+        .get_session_token()
+        .serial_number(mfa_serial)
+        .token_code(token_code)
+        .send()
+        .await
+        .context("Failed to get an MFA session token")?;
+
+    convert_credentials(output.credentials(), "aaa-mfa")
+}
+
+pub async fn assume_role_with_saml(
+    region: Option<&str>,
+    role_arn: &str,
+    principal_arn: &str,
+    saml_assertion: &str,
+    duration_seconds: Option<i32>,
+) -> Result<Credentials> {
+    let config = sdk_config(region, None);
+    let client = aws_sdk_sts::Client::new(&config);
+
+    let output = client
+        .assume_role_with_saml()
+        .role_arn(role_arn)
+        .principal_arn(principal_arn)
+        .saml_assertion(saml_assertion)
+        .set_duration_seconds(duration_seconds)
+        .send()
+        .await
+        .with_context(|| format!("Failed to assume role '{}' with SAML", role_arn))?;
+
+    convert_credentials(output.credentials(), "aaa-saml")
+}
+
+pub async fn assume_role_with_web_identity(
+    region: Option<&str>,
+    role_arn: &str,
+    role_session_name: &str,
+    web_identity_token: &str,
+    duration_seconds: Option<i32>,
+) -> Result<Credentials> {
+    let config = sdk_config(region, None);
+    let client = aws_sdk_sts::Client::new(&config);
+
+    let output = client
+        .assume_role_with_web_identity()
+        .role_arn(role_arn)
+        .role_session_name(role_session_name)
+        .web_identity_token(web_identity_token)
+        .set_duration_seconds(duration_seconds)
+        .send()
+        .await
+        .with_context(|| format!("Failed to assume role '{}' with web identity", role_arn))?;
+
+    convert_credentials(output.credentials(), "aaa-web-identity")
+}
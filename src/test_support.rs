@@ -0,0 +1,203 @@
+use crate::loopback;
+use std::env;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+// Environment variables are process-wide, so tests that change them run one
+// at a time
+static ENV_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// A scratch HOME with the environment lock held; the variables set through it
+// are put back and the directory removed when it is dropped
+pub struct TestEnv {
+    pub home: PathBuf,
+    saved: Vec<(String, Option<OsString>)>,
+    _lock: tokio::sync::MutexGuard<'static, ()>,
+}
+
+impl TestEnv {
+    pub async fn new(name: &str) -> TestEnv {
+        let lock = ENV_LOCK.lock().await;
+
+        let home = env::temp_dir().join(format!("aaa-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&home);
+        std::fs::create_dir_all(&home).expect("create test HOME");
+
+        let mut test_env = TestEnv {
+            home: home.clone(),
+            saved: Vec::new(),
+            _lock: lock,
+        };
+        test_env.set("HOME", &home);
+        // Lets the login flows "open" their URLs without starting a browser
+        test_env.set("BROWSER", "true");
+        // Keeps the tests away from an aaa agent the user may be running
+        test_env.set("AAA_AGENT_SOCKET", home.join("agent.sock"));
+        test_env
+    }
+
+    pub fn set(&mut self, key: &str, value: impl AsRef<std::ffi::OsStr>) {
+        self.save(key);
+        env::set_var(key, value);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.save(key);
+        env::remove_var(key);
+    }
+
+    fn save(&mut self, key: &str) {
+        if !self.saved.iter().any(|(saved, _)| saved == key) {
+            self.saved.push((key.to_string(), env::var_os(key)));
+        }
+    }
+}
+
+impl Drop for TestEnv {
+    fn drop(&mut self) {
+        for (key, value) in self.saved.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(&key, value),
+                None => env::remove_var(&key),
+            }
+        }
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+// A request as the stand-in received it
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub at: Instant,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Received {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // A field of a form-encoded body
+    pub fn form(&self, key: &str) -> Option<String> {
+        url::form_urlencoded::parse(self.body.as_bytes())
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+pub struct Reply {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Reply {
+    pub fn json(status: &'static str, body: serde_json::Value) -> Reply {
+        Reply {
+            status,
+            content_type: loopback::JSON,
+            body: body.to_string(),
+        }
+    }
+
+    pub fn text(status: &'static str, content_type: &'static str, body: String) -> Reply {
+        Reply {
+            status,
+            content_type,
+            body,
+        }
+    }
+}
+
+// Loopback stand-in for a remote service: answers every request with what the
+// handler returns for it and the requests received before it
+pub struct StandIn {
+    pub url: String,
+    received: Arc<Mutex<Vec<Received>>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for StandIn {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl StandIn {
+    pub async fn start<F>(handler: F) -> StandIn
+    where
+        F: Fn(&Received, &[Received]) -> Reply + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .expect("bind stand-in");
+        let url = format!(
+            "http://{}",
+            listener.local_addr().expect("stand-in address")
+        );
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let handler = Arc::new(handler);
+        let log = received.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                let log = log.clone();
+
+                tokio::spawn(async move {
+                    let Ok(request) = loopback::read_request(&mut stream).await else {
+                        return;
+                    };
+                    let request = Received {
+                        at: Instant::now(),
+                        path: request.path,
+                        headers: request.headers,
+                        body: request.body,
+                    };
+
+                    let reply = {
+                        let mut log = log.lock().unwrap();
+                        let reply = handler(&request, &log);
+                        log.push(request);
+                        reply
+                    };
+
+                    let _ = loopback::write_response(
+                        &mut stream,
+                        reply.status,
+                        reply.content_type,
+                        &reply.body,
+                    )
+                    .await;
+                });
+            }
+        });
+
+        StandIn {
+            url,
+            received,
+            task,
+        }
+    }
+
+    pub fn received(&self) -> Vec<Received> {
+        self.received.lock().unwrap().clone()
+    }
+
+    // Requests to one path, ignoring the query string
+    pub fn received_at(&self, path: &str) -> Vec<Received> {
+        self.received()
+            .into_iter()
+            .filter(|request| request.path.split('?').next() == Some(path))
+            .collect()
+    }
+}
@@ -0,0 +1,257 @@
+use crate::profile::WebIdentitySettings;
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+
+// Audience IAM expects by default for OIDC identity providers
+pub const DEFAULT_AUDIENCE: &str = "sts.amazonaws.com";
+// Variable the GitLab job declares under `id_tokens:`
+pub const DEFAULT_GITLAB_TOKEN_ENV: &str = "GITLAB_OIDC_TOKEN";
+
+const GITHUB_REQUEST_URL_ENV: &str = "ACTIONS_ID_TOKEN_REQUEST_URL";
+const GITHUB_REQUEST_TOKEN_ENV: &str = "ACTIONS_ID_TOKEN_REQUEST_TOKEN";
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+// Unverified claims of an OIDC token; STS does the actual validation
+#[derive(Debug, Deserialize)]
+pub struct TokenClaims {
+    pub iss: Option<String>,
+    pub sub: Option<String>,
+    pub aud: Option<Audience>,
+    pub exp: Option<i64>,
+}
+
+impl TokenClaims {
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.exp.and_then(|exp| DateTime::from_timestamp(exp, 0))
+    }
+
+    pub fn has_audience(&self, audience: &str) -> bool {
+        match &self.aud {
+            Some(Audience::One(aud)) => aud == audience,
+            Some(Audience::Many(auds)) => auds.iter().any(|aud| aud == audience),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GitHubTokenResponse {
+    value: String,
+}
+
+pub fn read_token_file(path: &str) -> Result<String> {
+    let token = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read web identity token file '{}'", path))?
+        .trim()
+        .to_string();
+
+    if token.is_empty() {
+        return Err(anyhow!("Web identity token file '{}' is empty", path));
+    }
+
+    Ok(token)
+}
+
+pub fn decode_claims(token: &str) -> Result<TokenClaims> {
+    let payload = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("Web identity token is not a JWT"))?;
+
+    let decoded = base64::engine::general_purpose::URL_SAFE_NO_PAD
+        .decode(payload.trim_end_matches('='))
+        .context("Web identity token payload is not valid base64")?;
+
+    serde_json::from_slice(&decoded).context("Web identity token payload is not valid JSON")
+}
+
+async fn fetch_github_token(audience: &str) -> Result<String> {
+    let request_url = env::var(GITHUB_REQUEST_URL_ENV).map_err(|_| {
+        anyhow!(
+            "{} is not set; the job needs the 'id-token: write' permission",
+            GITHUB_REQUEST_URL_ENV
+        )
+    })?;
+    let request_token = env::var(GITHUB_REQUEST_TOKEN_ENV)
+        .map_err(|_| anyhow!("{} is not set", GITHUB_REQUEST_TOKEN_ENV))?;
+
+    let mut url = url::Url::parse(&request_url)
+        .with_context(|| format!("{} is not a valid URL", GITHUB_REQUEST_URL_ENV))?;
+    url.query_pairs_mut().append_pair("audience", audience);
+
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(request_token)
+        .send()
+        .await
+        .context("Failed to reach the GitHub Actions OIDC endpoint")?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "GitHub Actions OIDC endpoint returned HTTP {}",
+            response.status()
+        ));
+    }
+
+    let token: GitHubTokenResponse = response
+        .json()
+        .await
+        .context("Failed to parse GitHub Actions OIDC response")?;
+
+    Ok(token.value)
+}
+
+// GitLab mints the token up front; its audience is set in .gitlab-ci.yml
+fn read_gitlab_token(token_env: &str, audience: Option<&str>) -> Result<String> {
+    let token = env::var(token_env)
+        .map_err(|_| {
+            anyhow!(
+                "{} is not set; declare it under 'id_tokens:' in .gitlab-ci.yml",
+                token_env
+            )
+        })?
+        .trim()
+        .to_string();
+
+    if let Some(audience) = audience {
+        if !decode_claims(&token)?.has_audience(audience) {
+            return Err(anyhow!(
+                "GitLab ID token in {} was not issued for audience '{}'",
+                token_env,
+                audience
+            ));
+        }
+    }
+
+    Ok(token)
+}
+
+// Returns the OIDC token of a web-identity profile, either from its token file
+// or from the CI provider the job runs on
+pub async fn fetch_token(web_identity: &WebIdentitySettings) -> Result<String> {
+    if let Some(token_file) = &web_identity.token_file {
+        return read_token_file(token_file);
+    }
+
+    let provider = web_identity
+        .provider
+        .as_deref()
+        .ok_or_else(|| anyhow!("Web identity token file is required but not configured"))?;
+    let audience = web_identity.audience.as_deref();
+    let token_env = web_identity
+        .token_env
+        .as_deref()
+        .unwrap_or(DEFAULT_GITLAB_TOKEN_ENV);
+
+    match provider {
+        "github" => fetch_github_token(audience.unwrap_or(DEFAULT_AUDIENCE)).await,
+        "gitlab" => read_gitlab_token(token_env, audience),
+        "auto" => {
+            if env::var_os(GITHUB_REQUEST_URL_ENV).is_some() {
+                fetch_github_token(audience.unwrap_or(DEFAULT_AUDIENCE)).await
+            } else if env::var_os(token_env).is_some() {
+                read_gitlab_token(token_env, audience)
+            } else {
+                Err(anyhow!(
+                    "No CI OIDC token available: neither {} nor {} is set",
+                    GITHUB_REQUEST_URL_ENV,
+                    token_env
+                ))
+            }
+        }
+        other => Err(anyhow!(
+            "Unknown web_identity_provider '{}' (expected github, gitlab or auto)",
+            other
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{Reply, StandIn, TestEnv};
+    use serde_json::json;
+
+    fn github_settings(audience: Option<&str>) -> WebIdentitySettings {
+        WebIdentitySettings {
+            role_arn: "arn:aws:iam::111111111111:role/Deploy".to_string(),
+            token_file: None,
+            provider: Some("github".to_string()),
+            audience: audience.map(String::from),
+            token_env: None,
+            role_session_name: None,
+            duration_seconds: None,
+        }
+    }
+
+    // Answers like the Actions runtime: a token for the requested audience
+    async fn start_github_stand_in() -> StandIn {
+        StandIn::start(|request, _| {
+            if request.header("Authorization") != Some("Bearer request-token") {
+                return Reply::json("401 Unauthorized", json!({ "message": "Bad credentials" }));
+            }
+            let audience = request.path.split("audience=").nth(1).unwrap_or_default();
+            Reply::json(
+                "200 OK",
+                json!({ "value": format!("token-for-{}", audience) }),
+            )
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn github_token_is_requested_for_the_audience() {
+        let mut test_env = TestEnv::new("github-token").await;
+        let github = start_github_stand_in().await;
+        // The runner hands out the URL with its own query string
+        test_env.set(
+            GITHUB_REQUEST_URL_ENV,
+            format!("{}/oidc/token?api-version=2.0", github.url),
+        );
+        test_env.set(GITHUB_REQUEST_TOKEN_ENV, "request-token");
+
+        let token = fetch_token(&github_settings(Some("my-audience")))
+            .await
+            .unwrap();
+        assert_eq!(token, "token-for-my-audience");
+
+        let requests = github.received_at("/oidc/token");
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].path,
+            "/oidc/token?api-version=2.0&audience=my-audience"
+        );
+
+        // auto prefers GitHub when the runner variables are there, and STS is
+        // the default audience
+        let mut auto = github_settings(None);
+        auto.provider = Some("auto".to_string());
+        let token = fetch_token(&auto).await.unwrap();
+        assert_eq!(token, format!("token-for-{}", DEFAULT_AUDIENCE));
+    }
+
+    #[tokio::test]
+    async fn github_token_request_reports_failures() {
+        let mut test_env = TestEnv::new("github-token-failure").await;
+        let github = start_github_stand_in().await;
+        test_env.set(GITHUB_REQUEST_URL_ENV, format!("{}/oidc/token", github.url));
+        test_env.set(GITHUB_REQUEST_TOKEN_ENV, "expired-token");
+
+        let error = fetch_token(&github_settings(None)).await.unwrap_err();
+        assert!(error.to_string().contains("HTTP 401"));
+
+        test_env.remove(GITHUB_REQUEST_URL_ENV);
+        let error = fetch_token(&github_settings(None)).await.unwrap_err();
+        assert!(error.to_string().contains("id-token: write"));
+    }
+}